@@ -6,8 +6,10 @@ extern crate image;
 
 use clap::{Arg, App};
 use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
 use raytracer::scene::*;
-use image::ImageFormat;
+use raytracer::camera::Eye;
+use image::{DynamicImage, GenericImage, ImageFormat};
 
 fn main() {
     let app = App::new("raytracer")
@@ -21,16 +23,146 @@ fn main() {
         .arg(Arg::with_name("image")
             .help("Sets the output image file")
             .required(true)
-            .index(2));
+            .index(2))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .help("Sets the format of the scene file")
+            .takes_value(true)
+            .possible_values(&["json", "gltf"])
+            .default_value("json"))
+        .arg(Arg::with_name("width")
+            .long("width")
+            .help("Sets the image width when importing a glTF scene")
+            .takes_value(true)
+            .default_value("800")
+            .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("height")
+            .long("height")
+            .help("Sets the image height when importing a glTF scene")
+            .takes_value(true)
+            .default_value("600")
+            .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("stereo-layout")
+            .long("stereo-layout")
+            .help("Sets how the eyes of a stereo camera are written out")
+            .takes_value(true)
+            .possible_values(&["side-by-side", "top-bottom", "separate"])
+            .default_value("side-by-side"))
+        .arg(Arg::with_name("frames")
+            .long("frames")
+            .help("Renders an animation frame range (start..end, end exclusive) as a numbered \
+                   image sequence")
+            .takes_value(true)
+            .validator(|s| parse_frames(&s).map(|_| ())))
+        .arg(Arg::with_name("samples")
+            .long("samples")
+            .help("Overrides the scene's number of samples per pixel")
+            .takes_value(true)
+            .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("adaptive")
+            .long("adaptive")
+            .help("Samples only pixels whose contrast with a neighbor exceeds this threshold \
+                   (0 to 1), instead of every pixel")
+            .takes_value(true)
+            .validator(|s| s.parse::<f32>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("adaptive-depth")
+            .long("adaptive-depth")
+            .help("Sets how many times adaptive sampling may split a pixel into quarters")
+            .takes_value(true)
+            .default_value("2")
+            .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("threads")
+            .long("threads")
+            .help("Sets the number of threads to render with; 0 uses one per core")
+            .takes_value(true)
+            .default_value("0")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())));
     let matches = app.get_matches();
 
-    let scene_path = matches.value_of("scene").unwrap();
-    let scene_file = File::open(scene_path).expect("File not found");
+    let scene_path = Path::new(matches.value_of("scene").unwrap());
+    let image_path = Path::new(matches.value_of("image").unwrap());
 
-    let image_path = matches.value_of("image").unwrap();
+    let mut scene: Scene = match matches.value_of("format").unwrap() {
+        "gltf" => {
+            let width = matches.value_of("width").unwrap().parse().unwrap();
+            let height = matches.value_of("height").unwrap().parse().unwrap();
+            raytracer::gltf::load_scene(scene_path, width, height).unwrap()
+        }
+        _ => {
+            let scene_file = File::open(scene_path).expect("File not found");
+            serde_json::from_reader(scene_file).unwrap()
+        }
+    };
+
+    if let Some(samples) = matches.value_of("samples") {
+        scene.samples_per_pixel = samples.parse().unwrap();
+    }
+    if let Some(threshold) = matches.value_of("adaptive") {
+        scene.adaptive_sampling = Some(AdaptiveSampling {
+            threshold: threshold.parse().unwrap(),
+            max_depth: matches.value_of("adaptive-depth").unwrap().parse().unwrap(),
+        });
+    }
+
+    let layout = matches.value_of("stereo-layout").unwrap();
+    let threads = matches.value_of("threads").unwrap().parse().unwrap();
+    match matches.value_of("frames") {
+        Some(frames) => {
+            let (start, end) = parse_frames(frames).unwrap();
+            for frame in start..end {
+                scene.set_frame(frame as f64);
+                let frame_path = suffixed_path(image_path, &format!("{:04}", frame));
+                render_frame(&mut scene, &frame_path, layout, threads);
+            }
+        }
+        None => render_frame(&mut scene, image_path, layout, threads),
+    }
+}
+
+fn parse_frames(frames: &str) -> Result<(u32, u32), String> {
+    let mut parts = frames.splitn(2, "..");
+    let start = parts.next().and_then(|s| s.parse().ok());
+    let end = parts.next().and_then(|s| s.parse().ok());
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => Ok((start, end)),
+        _ => Err(format!("Invalid frame range {:?}, expected start..end", frames)),
+    }
+}
 
-    let scene: Scene = serde_json::from_reader(scene_file).unwrap();
+fn render_frame(scene: &mut Scene, image_path: &Path, layout: &str, threads: usize) {
+    if scene.camera.stereo.is_none() {
+        save_image(&render_scene(scene, threads), image_path);
+        return;
+    }
 
+    let camera = scene.camera.clone();
+    scene.camera = camera.for_eye(Eye::Left);
+    let left = render_scene(scene, threads);
+    scene.camera = camera.for_eye(Eye::Right);
+    let right = render_scene(scene, threads);
+    scene.camera = camera;
+
+    match layout {
+        "separate" => {
+            save_image(&left, &suffixed_path(image_path, "left"));
+            save_image(&right, &suffixed_path(image_path, "right"));
+        }
+        "top-bottom" => {
+            let mut image = DynamicImage::new_rgb8(scene.width, scene.height * 2);
+            image.copy_from(&left, 0, 0);
+            image.copy_from(&right, 0, scene.height);
+            save_image(&image, image_path);
+        }
+        _ => {
+            let mut image = DynamicImage::new_rgb8(scene.width * 2, scene.height);
+            image.copy_from(&left, 0, 0);
+            image.copy_from(&right, scene.width, 0);
+            save_image(&image, image_path);
+        }
+    }
+}
+
+fn render_scene(scene: &Scene, threads: usize) -> DynamicImage {
     let block = raytracer::ViewBlock {
         x: 0,
         y: 0,
@@ -38,8 +170,19 @@ fn main() {
         height: scene.height,
     };
 
-    let image = raytracer::render(&block, &scene);
+    raytracer::render(&block, scene, threads)
+}
+
+fn suffixed_path(image_path: &Path, suffix: &str) -> PathBuf {
+    let stem = image_path.file_stem().unwrap().to_string_lossy();
+    let mut file_name = format!("{}_{}", stem, suffix);
+    if let Some(extension) = image_path.extension() {
+        file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+    }
+    image_path.with_file_name(file_name)
+}
 
+fn save_image(image: &DynamicImage, image_path: &Path) {
     let mut image_file =
         OpenOptions::new().write(true).truncate(true).create(true).open(image_path).unwrap();
     image.save(&mut image_file, ImageFormat::PNG).unwrap();
@@ -5,7 +5,10 @@ extern crate serde_json;
 use raytracer::point::Point;
 use raytracer::vector::Vector3;
 use raytracer::scene::*;
+use raytracer::bvh::Bvh;
+use raytracer::camera::{Camera, FisheyeMapping, FovAxis, Projection, Shutter};
 use raytracer::ViewBlock;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
@@ -31,23 +34,133 @@ pub extern "C" fn scene_from_json(json: *const c_char) -> *mut Scene {
 pub extern "C" fn scene_new(width: u32,
                             height: u32,
                             fov: f64,
+                            fov_axis: FovAxis,
                             shadow_bias: f64,
                             max_recursion_depth: u32)
                             -> *mut Scene {
-    let scene = Box::new(Scene {
+    let mut scene = Box::new(Scene {
         width: width,
         height: height,
-        fov: fov,
+        camera: Camera {
+            position: Point::zero(),
+            look_at: Point {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            up: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            projection: Projection::Perspective,
+            fov: fov,
+            fov_axis: fov_axis,
+            aperture: 0.0,
+            focal_distance: 1.0,
+            lens_shift: 0.0,
+            stereo: None,
+            shutter: Shutter::default(),
+        },
 
+        definitions: BTreeMap::new(),
         elements: vec![],
         lights: vec![],
 
         shadow_bias: shadow_bias,
         max_recursion_depth: max_recursion_depth,
+        samples_per_pixel: 1,
+        adaptive_sampling: None,
+        animation: vec![],
+        bvh: Bvh::default(),
     });
+    scene.build_bvh();
     Box::into_raw(scene)
 }
 
+#[no_mangle]
+pub extern "C" fn scene_set_camera(scene: *mut Scene,
+                                   position: *const Point,
+                                   look_at: *const Point,
+                                   up: *const Vector3,
+                                   fov: f64) {
+    if scene.is_null() || position.is_null() || look_at.is_null() || up.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+    {
+        let mut scene_ref = &mut *scene;
+        let camera = &mut scene_ref.camera;
+        camera.position = unsafe { &*position }.clone();
+        camera.look_at = unsafe { &*look_at }.clone();
+        camera.up = unsafe { &*up }.normalize();
+        camera.fov = fov;
+    }
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+#[no_mangle]
+pub extern "C" fn scene_set_projection(scene: *mut Scene, projection: *const Projection) {
+    if scene.is_null() || projection.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+    {
+        let mut scene_ref = &mut *scene;
+        scene_ref.camera.projection = unsafe { &*projection }.clone();
+    }
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+#[no_mangle]
+pub extern "C" fn scene_set_samples_per_pixel(scene: *mut Scene, samples: u32) {
+    if scene.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+    scene.samples_per_pixel = samples;
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+/// Turns on adaptive sampling with the given contrast threshold and maximum depth of splitting
+/// each pixel, or turns it off if `max_depth` is zero.
+#[no_mangle]
+pub extern "C" fn scene_set_adaptive_sampling(scene: *mut Scene, threshold: f32, max_depth: u32) {
+    if scene.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+    scene.adaptive_sampling = if max_depth > 0 {
+        Some(AdaptiveSampling {
+            threshold: threshold,
+            max_depth: max_depth,
+        })
+    } else {
+        None
+    };
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+#[no_mangle]
+pub extern "C" fn scene_set_frame(scene: *mut Scene, frame: f64) {
+    if scene.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+    scene.set_frame(frame);
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
 #[no_mangle]
 pub extern "C" fn scene_add_sphere(scene: *mut Scene,
                                    center: *const Point,
@@ -63,9 +176,10 @@ pub extern "C" fn scene_add_sphere(scene: *mut Scene,
             center: unsafe { &*center }.clone(),
             radius: radius,
             material: rust_material,
+            velocity: Vector3::zero(),
         };
         let mut scene_ref = &mut *scene;
-        scene_ref.elements.push(Element::Sphere(sphere));
+        scene_ref.add_element(Element::Sphere(sphere));
     }
 
     //Don't free the scene
@@ -77,6 +191,16 @@ pub extern "C" fn scene_add_plane(scene: *mut Scene,
                                   origin: *const Point,
                                   normal: *const Vector3,
                                   material: *const CMaterial) {
+    scene_add_plane_ex(scene, origin, normal, false, material);
+}
+
+/// Like `scene_add_plane`, but can make the plane visible from both sides.
+#[no_mangle]
+pub extern "C" fn scene_add_plane_ex(scene: *mut Scene,
+                                     origin: *const Point,
+                                     normal: *const Vector3,
+                                     double_sided: bool,
+                                     material: *const CMaterial) {
     if scene.is_null() || origin.is_null() || normal.is_null() || material.is_null() {
         return;
     }
@@ -87,9 +211,223 @@ pub extern "C" fn scene_add_plane(scene: *mut Scene,
             origin: unsafe { (&*origin) }.clone(),
             normal: unsafe { (&*normal) }.normalize(),
             material: rust_material,
+            velocity: Vector3::zero(),
+            double_sided: double_sided,
+        };
+        let mut scene_ref = &mut *scene;
+        scene_ref.add_element(Element::Plane(plane));
+    }
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+/// Adds a box spanning `min` to `max`. `rotation` may be null, or point to rotations in degrees
+/// about the X, Y and Z axes.
+#[no_mangle]
+pub extern "C" fn scene_add_box(scene: *mut Scene,
+                                min: *const Point,
+                                max: *const Point,
+                                rotation: *const Vector3,
+                                material: *const CMaterial) {
+    if scene.is_null() || min.is_null() || max.is_null() || material.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+
+    if let Some(rust_material) = unsafe { &*material }.to_rust() {
+        let rotation = if rotation.is_null() {
+            Vector3::zero()
+        } else {
+            unsafe { &*rotation }.clone()
+        };
+        let cuboid = Cuboid {
+            min: unsafe { &*min }.clone(),
+            max: unsafe { &*max }.clone(),
+            rotation: rotation,
+            material: rust_material,
+        };
+        let mut scene_ref = &mut *scene;
+        scene_ref.add_element(Element::Box(cuboid));
+    }
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+#[no_mangle]
+pub extern "C" fn scene_add_disk(scene: *mut Scene,
+                                 center: *const Point,
+                                 normal: *const Vector3,
+                                 radius: f64,
+                                 material: *const CMaterial) {
+    scene_add_disk_ex(scene, center, normal, radius, false, material);
+}
+
+/// Like `scene_add_disk`, but can make the disk visible from both sides.
+#[no_mangle]
+pub extern "C" fn scene_add_disk_ex(scene: *mut Scene,
+                                    center: *const Point,
+                                    normal: *const Vector3,
+                                    radius: f64,
+                                    double_sided: bool,
+                                    material: *const CMaterial) {
+    if scene.is_null() || center.is_null() || normal.is_null() || material.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+
+    if let Some(rust_material) = unsafe { &*material }.to_rust() {
+        let disk = Disk {
+            center: unsafe { &*center }.clone(),
+            normal: unsafe { &*normal }.normalize(),
+            radius: radius,
+            material: rust_material,
+            double_sided: double_sided,
         };
         let mut scene_ref = &mut *scene;
-        scene_ref.elements.push(Element::Plane(plane));
+        scene_ref.add_element(Element::Disk(disk));
+    }
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+#[no_mangle]
+pub extern "C" fn scene_add_cylinder(scene: *mut Scene,
+                                     base: *const Point,
+                                     axis: *const Vector3,
+                                     radius: f64,
+                                     height: f64,
+                                     capped: bool,
+                                     material: *const CMaterial) {
+    if scene.is_null() || base.is_null() || axis.is_null() || material.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+
+    if let Some(rust_material) = unsafe { &*material }.to_rust() {
+        let cylinder = Cylinder {
+            base: unsafe { &*base }.clone(),
+            axis: unsafe { &*axis }.normalize(),
+            radius: radius,
+            height: height,
+            capped: capped,
+            material: rust_material,
+        };
+        let mut scene_ref = &mut *scene;
+        scene_ref.add_element(Element::Cylinder(cylinder));
+    }
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+#[no_mangle]
+pub extern "C" fn scene_add_cone(scene: *mut Scene,
+                                 base: *const Point,
+                                 axis: *const Vector3,
+                                 radius: f64,
+                                 height: f64,
+                                 capped: bool,
+                                 material: *const CMaterial) {
+    if scene.is_null() || base.is_null() || axis.is_null() || material.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+
+    if let Some(rust_material) = unsafe { &*material }.to_rust() {
+        let cone = Cone {
+            base: unsafe { &*base }.clone(),
+            axis: unsafe { &*axis }.normalize(),
+            radius: radius,
+            height: height,
+            capped: capped,
+            material: rust_material,
+        };
+        let mut scene_ref = &mut *scene;
+        scene_ref.add_element(Element::Cone(cone));
+    }
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+#[no_mangle]
+pub extern "C" fn scene_add_torus(scene: *mut Scene,
+                                  center: *const Point,
+                                  axis: *const Vector3,
+                                  major_radius: f64,
+                                  minor_radius: f64,
+                                  material: *const CMaterial) {
+    if scene.is_null() || center.is_null() || axis.is_null() || material.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+
+    if let Some(rust_material) = unsafe { &*material }.to_rust() {
+        let torus = Torus {
+            center: unsafe { &*center }.clone(),
+            axis: unsafe { &*axis }.normalize(),
+            major_radius: major_radius,
+            minor_radius: minor_radius,
+            material: rust_material,
+        };
+        let mut scene_ref = &mut *scene;
+        scene_ref.add_element(Element::Torus(torus));
+    }
+
+    //Don't free the scene
+    Box::into_raw(scene);
+}
+
+/// Adds a triangle. `vertices` must point to three points; `normals` and `texture_coords` may
+/// be null, or point to one value per vertex.
+#[no_mangle]
+pub extern "C" fn scene_add_triangle(scene: *mut Scene,
+                                     vertices: *const Point,
+                                     normals: *const Vector3,
+                                     texture_coords: *const TextureCoords,
+                                     material: *const CMaterial) {
+    scene_add_triangle_ex(scene, vertices, normals, texture_coords, false, material);
+}
+
+/// Like `scene_add_triangle`, but can make the triangle visible from both sides.
+#[no_mangle]
+pub extern "C" fn scene_add_triangle_ex(scene: *mut Scene,
+                                        vertices: *const Point,
+                                        normals: *const Vector3,
+                                        texture_coords: *const TextureCoords,
+                                        double_sided: bool,
+                                        material: *const CMaterial) {
+    if scene.is_null() || vertices.is_null() || material.is_null() {
+        return;
+    }
+    let mut scene = unsafe { Box::from_raw(scene) };
+
+    if let Some(rust_material) = unsafe { &*material }.to_rust() {
+        let vertices = unsafe { slice::from_raw_parts(vertices, 3) };
+        let normals = if normals.is_null() {
+            None
+        } else {
+            let normals = unsafe { slice::from_raw_parts(normals, 3) };
+            Some([normals[0].normalize(), normals[1].normalize(), normals[2].normalize()])
+        };
+        let texture_coords = if texture_coords.is_null() {
+            None
+        } else {
+            let coords = unsafe { slice::from_raw_parts(texture_coords, 3) };
+            Some([coords[0], coords[1], coords[2]])
+        };
+        let triangle = Triangle {
+            vertices: [vertices[0], vertices[1], vertices[2]],
+            normals: normals,
+            texture_coords: texture_coords,
+            material: rust_material,
+            double_sided: double_sided,
+        };
+        let mut scene_ref = &mut *scene;
+        scene_ref.add_element(Element::Triangle(triangle));
     }
 
     //Don't free the scene
@@ -159,11 +497,14 @@ pub extern "C" fn scene_get_json(scene: *mut Scene) -> *mut c_char {
     result
 }
 
+/// Renders the block into `buffer` as RGBA pixels, using `threads` threads, or one per core if
+/// that is zero.
 #[no_mangle]
 pub extern "C" fn scene_render(scene: *mut Scene,
                                block: *const ViewBlock,
                                buffer: *mut u8,
-                               length: usize) {
+                               length: usize,
+                               threads: u32) {
     if scene.is_null() || block.is_null() || buffer.is_null() {
         return;
     }
@@ -172,7 +513,7 @@ pub extern "C" fn scene_render(scene: *mut Scene,
     let buffer = unsafe { slice::from_raw_parts_mut(buffer, length) };
 
     if let Some(mut image) = image::ImageBuffer::from_raw(block.width, block.height, buffer) {
-        raytracer::render_into(block, &*scene, &mut image);
+        raytracer::render_into(block, &*scene, &mut image, threads as usize);
     }
 
     //Don't free the scene
@@ -288,6 +629,49 @@ pub extern "C" fn surfacetype_free(ptr: *mut SurfaceType) {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn projection_perspective_new() -> *mut Projection {
+    let projection = Box::new(Projection::Perspective);
+    Box::into_raw(projection)
+}
+
+#[no_mangle]
+pub extern "C" fn projection_orthographic_new(view_width: f64) -> *mut Projection {
+    let projection = Box::new(Projection::Orthographic { view_width: view_width });
+    Box::into_raw(projection)
+}
+
+#[no_mangle]
+pub extern "C" fn projection_equirectangular_new() -> *mut Projection {
+    let projection = Box::new(Projection::Equirectangular);
+    Box::into_raw(projection)
+}
+
+#[no_mangle]
+pub extern "C" fn projection_fisheye_new(mapping: FisheyeMapping, fov: f64) -> *mut Projection {
+    let projection = Box::new(Projection::Fisheye {
+        mapping: mapping,
+        fov: fov,
+    });
+    Box::into_raw(projection)
+}
+
+#[no_mangle]
+pub extern "C" fn projection_cylindrical_new(fov: f64) -> *mut Projection {
+    let projection = Box::new(Projection::Cylindrical { fov: fov });
+    Box::into_raw(projection)
+}
+
+#[no_mangle]
+pub extern "C" fn projection_free(ptr: *mut Projection) {
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        Box::from_raw(ptr);
+    }
+}
+
 #[repr(C)]
 pub struct CMaterial {
     coloration: *const CColoration,
@@ -15,6 +15,10 @@ ffi.cdef("""
         float red, green, blue;
     } color_t;
 
+    typedef struct {
+        float x, y;
+    } texture_coords_t;
+
     typedef void* coloration;
     coloration coloration_color_new(float red, float green, float blue);
     coloration coloration_texture_new(char *path);
@@ -26,6 +30,17 @@ ffi.cdef("""
     surfacetype surfacetype_refractive_new(float index, float transparency);
     void surfacetype_free(surfacetype);
 
+    typedef void* projection;
+    projection projection_perspective_new();
+    projection projection_orthographic_new(double view_width);
+    projection projection_equirectangular_new();
+    typedef enum {
+        FISHEYE_EQUIDISTANT, FISHEYE_EQUISOLID
+    } fisheye_mapping_t;
+    projection projection_fisheye_new(fisheye_mapping_t mapping, double fov);
+    projection projection_cylindrical_new(double fov);
+    void projection_free(projection);
+
     typedef struct {
         coloration coloration;
         surfacetype surface;
@@ -36,19 +51,51 @@ ffi.cdef("""
         uint32_t x, y, width, height;
     } block_t;
 
+    typedef enum {
+        FOV_HORIZONTAL, FOV_VERTICAL, FOV_DIAGONAL
+    } fov_axis_t;
+
     typedef void* scene;
     scene scene_new(uint32_t width, uint32_t height,
-        double fov, double shadow_bias, uint32_t max_recursion_depth);
+        double fov, fov_axis_t fov_axis, double shadow_bias,
+        uint32_t max_recursion_depth);
     scene scene_from_json(char *buffer);
+    void scene_set_camera(scene, const point_t *position, const point_t *look_at,
+        const vector_t *up, double fov);
+    void scene_set_projection(scene, projection);
+    void scene_set_samples_per_pixel(scene, uint32_t samples);
+    void scene_set_adaptive_sampling(scene, float threshold, uint32_t max_depth);
+    void scene_set_frame(scene, double frame);
     void scene_add_sphere(scene, const point_t *center, double radius,
         const material_t *material);
     void scene_add_plane(scene, const point_t *origin, const vector_t *normal,
         const material_t *material);
+    void scene_add_plane_ex(scene, const point_t *origin, const vector_t *normal,
+        bool double_sided, const material_t *material);
+    void scene_add_box(scene, const point_t *min, const point_t *max,
+        const vector_t *rotation, const material_t *material);
+    void scene_add_disk(scene, const point_t *center, const vector_t *normal,
+        double radius, const material_t *material);
+    void scene_add_disk_ex(scene, const point_t *center, const vector_t *normal,
+        double radius, bool double_sided, const material_t *material);
+    void scene_add_cylinder(scene, const point_t *base, const vector_t *axis,
+        double radius, double height, bool capped, const material_t *material);
+    void scene_add_cone(scene, const point_t *base, const vector_t *axis,
+        double radius, double height, bool capped, const material_t *material);
+    void scene_add_torus(scene, const point_t *center, const vector_t *axis,
+        double major_radius, double minor_radius, const material_t *material);
+    void scene_add_triangle(scene, const point_t *vertices,
+        const vector_t *normals, const texture_coords_t *texture_coords,
+        const material_t *material);
+    void scene_add_triangle_ex(scene, const point_t *vertices,
+        const vector_t *normals, const texture_coords_t *texture_coords,
+        bool double_sided, const material_t *material);
     void scene_add_spherical_light(scene, const point_t *position,
         const color_t *color, float intensity);
     void scene_add_directional_light(scene, const vector_t *direction,
         const color_t *color, float intensity);
-    void scene_render(scene, const block_t *block, char *buffer, size_t length);
+    void scene_render(scene, const block_t *block, char *buffer, size_t length,
+        uint32_t threads);
     char *scene_get_json(scene);
     void scene_free(scene);
     void string_free(char *string);
@@ -115,11 +162,55 @@ class Scene(object):
         C.scene_free(self.__obj)
         self.__obj = None
 
+    def set_camera(self, position, look_at, up, fov):
+        C.scene_set_camera(self.__obj, position, look_at, up, fov)
+
+    def set_projection(self, projection):
+        C.scene_set_projection(self.__obj, projection.get_raw())
+
+    def set_samples_per_pixel(self, samples):
+        C.scene_set_samples_per_pixel(self.__obj, samples)
+
+    def set_adaptive_sampling(self, threshold=0.1, max_depth=2):
+        C.scene_set_adaptive_sampling(self.__obj, threshold, max_depth)
+
+    def set_frame(self, frame):
+        C.scene_set_frame(self.__obj, frame)
+
     def add_sphere(self, center, radius, material):
         C.scene_add_sphere(self.__obj, center, radius, material)
 
-    def add_plane(self, origin, normal, material):
-        C.scene_add_plane(self.__obj, origin, normal, material)
+    def add_plane(self, origin, normal, material, double_sided=False):
+        C.scene_add_plane_ex(self.__obj, origin, normal, double_sided, material)
+
+    def add_box(self, min, max, material, rotation=None):
+        if rotation is None:
+            rotation = ffi.NULL
+        C.scene_add_box(self.__obj, min, max, rotation, material)
+
+    def add_disk(self, center, normal, radius, material, double_sided=False):
+        C.scene_add_disk_ex(self.__obj, center, normal, radius, double_sided, material)
+
+    def add_cylinder(self, base, axis, radius, height, material, capped=True):
+        C.scene_add_cylinder(self.__obj, base, axis, radius, height, capped, material)
+
+    def add_cone(self, base, axis, radius, height, material, capped=True):
+        C.scene_add_cone(self.__obj, base, axis, radius, height, capped, material)
+
+    def add_torus(self, center, axis, major_radius, minor_radius, material):
+        C.scene_add_torus(self.__obj, center, axis, major_radius, minor_radius, material)
+
+    def add_triangle(self, vertices, material, normals=None, texture_coords=None,
+                     double_sided=False):
+        c_vertices = ffi.new("point_t[3]", [(v.x, v.y, v.z) for v in vertices])
+        c_normals = ffi.NULL
+        if normals is not None:
+            c_normals = ffi.new("vector_t[3]", [(n.x, n.y, n.z) for n in normals])
+        c_coords = ffi.NULL
+        if texture_coords is not None:
+            c_coords = ffi.new("texture_coords_t[3]", texture_coords)
+        C.scene_add_triangle_ex(self.__obj, c_vertices, c_normals, c_coords, double_sided,
+                                material)
 
     def add_spherical_light(self, position, color, intensity):
         C.scene_add_spherical_light(self.__obj, position, color, intensity)
@@ -133,17 +224,17 @@ class Scene(object):
         self.__width = width
         self.__height = height
 
-    def render_image(self):
+    def render_image(self, threads=0):
         pixel_format = "RGBA" #The raytracer only supports one format
         return Image.frombuffer(pixel_format, (self.__width, self.__height),
-            self.render_bytes(), "raw", pixel_format, 0, 1)
+            self.render_bytes(threads), "raw", pixel_format, 0, 1)
 
-    def render_bytes(self):
+    def render_bytes(self, threads=0):
         bytes_per_pixel = 4
         buffer_len = self.__width * self.__height * bytes_per_pixel
         buffer = ffi.new("char[]", buffer_len)
         view_block = block(self.__x, self.__y, self.__width, self.__height)
-        C.scene_render(self.__obj, view_block, buffer, buffer_len)
+        C.scene_render(self.__obj, view_block, buffer, buffer_len, threads)
         return ffi.buffer(buffer)
 
     def get_json(self):
@@ -161,8 +252,10 @@ class Scene(object):
         return Scene(None, None, obj)
 
     @staticmethod
-    def create(width, height, fov, shadow_bias, max_recursion_depth):
-        obj = C.scene_new(width, height, fov, shadow_bias, max_recursion_depth)
+    def create(width, height, fov, shadow_bias, max_recursion_depth,
+            fov_axis=C.FOV_VERTICAL):
+        obj = C.scene_new(width, height, fov, fov_axis, shadow_bias,
+            max_recursion_depth)
         return Scene(width, height, obj)
 
 class Coloration(object):
@@ -218,3 +311,42 @@ class SurfaceType(object):
 
     def get_raw(self):
         return self.__obj
+
+class Projection(object):
+    @staticmethod
+    def perspective():
+        projection = C.projection_perspective_new()
+        return Projection(projection)
+
+    @staticmethod
+    def orthographic(view_width):
+        projection = C.projection_orthographic_new(view_width)
+        return Projection(projection)
+
+    @staticmethod
+    def equirectangular():
+        projection = C.projection_equirectangular_new()
+        return Projection(projection)
+
+    @staticmethod
+    def fisheye(fov, mapping=C.FISHEYE_EQUIDISTANT):
+        projection = C.projection_fisheye_new(mapping, fov)
+        return Projection(projection)
+
+    @staticmethod
+    def cylindrical(fov):
+        projection = C.projection_cylindrical_new(fov)
+        return Projection(projection)
+
+    def __init__(self, obj):
+        self.__obj = obj
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, exc_type, exc_value, traceback):
+        C.projection_free(self.__obj)
+        self.__obj = None
+
+    def get_raw(self):
+        return self.__obj
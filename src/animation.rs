@@ -0,0 +1,185 @@
+use point::Point;
+use scene::{Color, Coloration, Scene};
+use serde::{Deserialize, Deserializer};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    /// Hold each key's value until the next key.
+    Step,
+    Linear,
+    /// Ease in and out of each key.
+    Smooth,
+}
+impl Default for Interpolation {
+    fn default() -> Interpolation {
+        Interpolation::Linear
+    }
+}
+
+pub trait Interpolate: Copy {
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+}
+impl Interpolate for f32 {
+    fn lerp(&self, other: &f32, t: f64) -> f32 {
+        self + (other - self) * t as f32
+    }
+}
+impl Interpolate for Point {
+    fn lerp(&self, other: &Point, t: f64) -> Point {
+        *self + (*other - *self) * t
+    }
+}
+impl Interpolate for Color {
+    fn lerp(&self, other: &Color, t: f64) -> Color {
+        Color {
+            red: self.red.lerp(&other.red, t),
+            green: self.green.lerp(&other.green, t),
+            blue: self.blue.lerp(&other.blue, t),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Keyframe<T> {
+    pub frame: f64,
+    pub value: T,
+}
+
+/// A sequence of keyframes, which must be sorted by frame. Keys are sorted as they are loaded.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Keyframes<T> {
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(deserialize_with = "load_keys", bound(deserialize = "T: Deserialize"))]
+    pub keys: Vec<Keyframe<T>>,
+}
+
+fn load_keys<T, D>(deserializer: D) -> Result<Vec<Keyframe<T>>, D::Error>
+    where T: Deserialize,
+          D: Deserializer
+{
+    let mut keys = Vec::<Keyframe<T>>::deserialize(deserializer)?;
+    if let Some(key) = keys.iter().find(|k| !k.frame.is_finite()) {
+        return Err(::serde::de::Error::custom(format!("Invalid keyframe frame: {}", key.frame)));
+    }
+    //A stable sort, so that keys on the same frame still jump from one value to the next.
+    keys.sort_by(|a, b| a.frame.partial_cmp(&b.frame).unwrap());
+    Ok(keys)
+}
+impl<T: Interpolate> Keyframes<T> {
+    /// Returns the value at the given frame, or `None` if there are no keys. Frames before the
+    /// first key or after the last take that key's value.
+    pub fn value_at(&self, frame: f64) -> Option<T> {
+        let next = match self.keys.iter().position(|k| k.frame > frame) {
+            Some(0) => return self.keys.first().map(|k| k.value),
+            Some(next) => next,
+            None => return self.keys.last().map(|k| k.value),
+        };
+        let start = &self.keys[next - 1];
+        let end = &self.keys[next];
+
+        let t = (frame - start.frame) / (end.frame - start.frame);
+        let t = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => t,
+            Interpolation::Smooth => t * t * (3.0 - 2.0 * t),
+        };
+        Some(start.value.lerp(&end.value, t))
+    }
+}
+
+/// An animated property of the scene. Elements and lights are referred to by their index in
+/// the scene's lists.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum Track {
+    CameraPosition(Keyframes<Point>),
+    CameraLookAt(Keyframes<Point>),
+    ElementCenter { element: usize, keys: Keyframes<Point> },
+    LightIntensity { light: usize, keys: Keyframes<f32> },
+    MaterialColor { element: usize, keys: Keyframes<Color> },
+}
+impl Track {
+    /// Checks that the element or light the track animates is in the scene.
+    pub fn check(&self, scene: &Scene) -> Result<(), String> {
+        match *self {
+            Track::ElementCenter { element, .. } |
+            Track::MaterialColor { element, .. } if element >= scene.elements.len() => {
+                Err(format!("Animation track refers to missing element {}", element))
+            }
+            Track::LightIntensity { light, .. } if light >= scene.lights.len() => {
+                Err(format!("Animation track refers to missing light {}", light))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(&self, scene: &mut Scene, frame: f64) {
+        match *self {
+            Track::CameraPosition(ref keys) => {
+                if let Some(position) = keys.value_at(frame) {
+                    scene.camera.position = position;
+                }
+            }
+            Track::CameraLookAt(ref keys) => {
+                if let Some(look_at) = keys.value_at(frame) {
+                    scene.camera.look_at = look_at;
+                }
+            }
+            Track::ElementCenter { element, ref keys } => {
+                if let (Some(center), Some(element)) = (keys.value_at(frame),
+                                                        scene.elements.get_mut(element)) {
+                    element.set_center(center);
+                }
+            }
+            Track::LightIntensity { light, ref keys } => {
+                if let (Some(intensity), Some(light)) = (keys.value_at(frame),
+                                                         scene.lights.get_mut(light)) {
+                    light.set_intensity(intensity);
+                }
+            }
+            Track::MaterialColor { element, ref keys } => {
+                if let (Some(color), Some(element)) = (keys.value_at(frame),
+                                                       scene.elements.get_mut(element)) {
+                    element.material_mut().coloration = Coloration::Color(color);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn keys_are_sorted_when_loaded() {
+        let keys: Keyframes<f32> = serde_json::from_str(r#"{"keys": [
+            {"frame": 10.0, "value": 1.0},
+            {"frame": 0.0, "value": 0.0},
+            {"frame": 5.0, "value": 3.0}
+        ]}"#)
+            .unwrap();
+        let frames: Vec<f64> = keys.keys.iter().map(|k| k.frame).collect();
+        assert_eq!(frames, vec![0.0, 5.0, 10.0]);
+        assert_eq!(keys.value_at(2.5), Some(1.5));
+        assert_eq!(keys.value_at(7.5), Some(2.0));
+    }
+
+    #[test]
+    fn tracks_must_refer_to_parts_of_the_scene() {
+        let scene = |track: &str| {
+            serde_json::from_str::<Scene>(&format!(r#"{{"width": 4, "height": 3, "elements": [],
+                "lights": [], "shadow_bias": 1e-13, "max_recursion_depth": 1,
+                "animation": [{}]}}"#,
+                                                   track))
+        };
+        let keys = r#"{"keys": [{"frame": 0.0, "value": 1.0}]}"#;
+        assert!(scene(&format!(r#"{{"LightIntensity": {{"light": 0, "keys": {}}}}}"#, keys))
+            .is_err());
+        let position = r#"{"keys": [{"frame": 0.0, "value": {"x": 0.0, "y": 0.0, "z": 0.0}}]}"#;
+        assert!(scene(&format!(r#"{{"ElementCenter": {{"element": 2, "keys": {}}}}}"#, position))
+            .is_err());
+        assert!(scene(&format!(r#"{{"CameraPosition": {}}}"#, position)).is_ok());
+    }
+}
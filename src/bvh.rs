@@ -0,0 +1,483 @@
+//! Bounding volume hierarchies, which let a ray skip everything whose bounding box it misses.
+
+use point::Point;
+use vector::Vector3;
+use rendering::Ray;
+use std::f64;
+
+/// Number of buckets the centroids are sorted into when looking for the cheapest split.
+const SAH_BINS: usize = 12;
+/// Cost of visiting a branch, relative to the cost of intersecting one primitive.
+const TRAVERSAL_COST: f64 = 0.125;
+/// Leaves this small are kept whenever splitting them wouldn't be any cheaper.
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+fn component(point: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+fn direction_component(direction: &Vector3, axis: usize) -> f64 {
+    match axis {
+        0 => direction.x,
+        1 => direction.y,
+        _ => direction.z,
+    }
+}
+
+impl BoundingBox {
+    /// A box containing nothing, which any other box can be added to.
+    pub fn empty() -> BoundingBox {
+        BoundingBox {
+            min: Point {
+                x: f64::INFINITY,
+                y: f64::INFINITY,
+                z: f64::INFINITY,
+            },
+            max: Point {
+                x: f64::NEG_INFINITY,
+                y: f64::NEG_INFINITY,
+                z: f64::NEG_INFINITY,
+            },
+        }
+    }
+
+    pub fn from_points(points: &[Point]) -> BoundingBox {
+        points.iter().fold(BoundingBox::empty(), |bounds, point| bounds.grow(point))
+    }
+
+    /// The box around a point, reaching `extent` from it along each axis.
+    pub fn around(center: &Point, extent: Vector3) -> BoundingBox {
+        BoundingBox {
+            min: *center - extent,
+            max: *center + extent,
+        }
+    }
+
+    pub fn grow(&self, point: &Point) -> BoundingBox {
+        BoundingBox {
+            min: Point {
+                x: self.min.x.min(point.x),
+                y: self.min.y.min(point.y),
+                z: self.min.z.min(point.z),
+            },
+            max: Point {
+                x: self.max.x.max(point.x),
+                y: self.max.y.max(point.y),
+                z: self.max.z.max(point.z),
+            },
+        }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        self.grow(&other.min).grow(&other.max)
+    }
+
+    /// The box covering only what both boxes cover. This is empty if they don't overlap.
+    pub fn intersection(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: Point {
+                x: self.min.x.max(other.min.x),
+                y: self.min.y.max(other.min.y),
+                z: self.min.z.max(other.min.z),
+            },
+            max: Point {
+                x: self.max.x.min(other.max.x),
+                y: self.max.y.min(other.max.y),
+                z: self.max.z.min(other.max.z),
+            },
+        }
+    }
+
+    /// Moves every face of the box outward by `amount`.
+    pub fn expand(&self, amount: f64) -> BoundingBox {
+        BoundingBox {
+            min: self.min - Vector3::from_one(amount),
+            max: self.max + Vector3::from_one(amount),
+        }
+    }
+
+    pub fn corners(&self) -> [Point; 8] {
+        let mut corners = [self.min; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            *corner = Point {
+                x: if i & 1 == 0 { self.min.x } else { self.max.x },
+                y: if i & 2 == 0 { self.min.y } else { self.max.y },
+                z: if i & 4 == 0 { self.min.z } else { self.max.z },
+            };
+        }
+        corners
+    }
+
+    pub fn centroid(&self) -> Point {
+        self.min + (self.max - self.min) * 0.5
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// Returns the distance at which the ray enters the box, or zero if it starts inside, as
+    /// long as that is no further than `max_distance`.
+    pub fn entry(&self, ray: &Ray, max_distance: f64) -> Option<f64> {
+        let mut near: f64 = 0.0;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let origin = component(&ray.origin, axis);
+            let inverse = 1.0 / direction_component(&ray.direction, axis);
+            let t0 = (component(&self.min, axis) - origin) * inverse;
+            let t1 = (component(&self.max, axis) - origin) * inverse;
+            //Rays parallel to a face that they lie in give NaN here, which min and max skip.
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        //Allow for rounding, so rays that graze flat boxes still go in.
+        if near <= far * (1.0 + 1e-12) {
+            Some(near)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Leaf {
+        bounds: BoundingBox,
+        start: usize,
+        end: usize,
+    },
+    Branch {
+        bounds: BoundingBox,
+        /// The axis the children were split along; `left` holds the lower centroids.
+        axis: usize,
+        left: usize,
+        right: usize,
+    },
+}
+
+struct Primitive {
+    index: usize,
+    bounds: BoundingBox,
+    centroid: Point,
+}
+
+/// A hierarchy of bounding boxes over a list of primitives, which are referred to by their
+/// index in that list. Primitives without bounds are kept aside and tested against every ray.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Indices of the bounded primitives, ordered so that each leaf covers a run of them.
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+    count: usize,
+}
+
+impl Bvh {
+    /// Builds the hierarchy by splitting wherever the surface area heuristic says is cheapest.
+    /// `bounds` has one entry per primitive, with `None` for those that are unbounded.
+    pub fn build(bounds: &[Option<BoundingBox>]) -> Bvh {
+        let mut bvh = Bvh { count: bounds.len(), ..Bvh::default() };
+        let mut primitives = vec![];
+        for (index, bounds) in bounds.iter().enumerate() {
+            match *bounds {
+                Some(bounds) if !bounds.is_empty() => {
+                    primitives.push(Primitive {
+                        index: index,
+                        bounds: bounds,
+                        centroid: bounds.centroid(),
+                    })
+                }
+                //An empty box can never be hit, so there's nothing to keep.
+                Some(_) => {}
+                None => bvh.unbounded.push(index),
+            }
+        }
+        if !primitives.is_empty() {
+            bvh.build_node(&mut primitives);
+        }
+        bvh
+    }
+
+    /// Number of primitives the hierarchy was built over.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn build_node(&mut self, primitives: &mut [Primitive]) -> usize {
+        let bounds = primitives.iter().fold(BoundingBox::empty(), |b, p| b.union(&p.bounds));
+        let node = self.nodes.len();
+
+        let split = match split(primitives, &bounds) {
+            Some(split) => split,
+            None => {
+                let start = self.indices.len();
+                self.indices.extend(primitives.iter().map(|p| p.index));
+                self.nodes.push(Node::Leaf {
+                    bounds: bounds,
+                    start: start,
+                    end: self.indices.len(),
+                });
+                return node;
+            }
+        };
+
+        //Reserve this node's place before its children take the following ones.
+        self.nodes.push(Node::Leaf {
+            bounds: bounds,
+            start: 0,
+            end: 0,
+        });
+        let (axis, middle) = split;
+        let (lower, upper) = primitives.split_at_mut(middle);
+        let left = self.build_node(lower);
+        let right = self.build_node(upper);
+        self.nodes[node] = Node::Branch {
+            bounds: bounds,
+            axis: axis,
+            left: left,
+            right: right,
+        };
+        node
+    }
+
+    /// Finds the nearest hit among the primitives the ray might reach. `intersect` tests a
+    /// single primitive, returning its hit and the distance to it.
+    pub fn nearest<T, F>(&self, ray: &Ray, mut intersect: F) -> Option<(f64, T)>
+        where F: FnMut(usize) -> Option<(f64, T)>
+    {
+        let mut nearest: Option<(f64, T)> = None;
+        {
+            let mut test = |index: usize, nearest: &mut Option<(f64, T)>| {
+                if let Some((distance, hit)) = intersect(index) {
+                    if nearest.as_ref().map_or(true, |&(nearest, _)| distance < nearest) {
+                        *nearest = Some((distance, hit));
+                    }
+                }
+            };
+
+            for &index in &self.unbounded {
+                test(index, &mut nearest);
+            }
+
+            let mut stack = if self.nodes.is_empty() { vec![] } else { vec![0] };
+            while let Some(node) = stack.pop() {
+                let limit = nearest.as_ref().map_or(f64::INFINITY, |&(distance, _)| distance);
+                match self.nodes[node] {
+                    Node::Leaf { ref bounds, start, end } => {
+                        if bounds.entry(ray, limit).is_some() {
+                            for &index in &self.indices[start..end] {
+                                test(index, &mut nearest);
+                            }
+                        }
+                    }
+                    Node::Branch { ref bounds, axis, left, right } => {
+                        if bounds.entry(ray, limit).is_some() {
+                            //Visit the child nearer the ray's origin first, so that its hits
+                            //can rule out the other.
+                            if direction_component(&ray.direction, axis) < 0.0 {
+                                stack.push(left);
+                                stack.push(right);
+                            } else {
+                                stack.push(right);
+                                stack.push(left);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        nearest
+    }
+}
+
+/// Picks where to split a set of primitives, by bucketing their centroids along each axis and
+/// comparing the surface area cost of splitting between each pair of buckets. Reorders the
+/// primitives so the first part holds the lower ones, and returns the axis and the size of that
+/// part, or `None` if they're best left as a leaf.
+fn split(primitives: &mut [Primitive], bounds: &BoundingBox) -> Option<(usize, usize)> {
+    if primitives.len() <= 1 {
+        return None;
+    }
+    let centroids = primitives.iter().fold(BoundingBox::empty(), |b, p| b.grow(&p.centroid));
+
+    let bin_of = |primitive: &Primitive, axis: usize| {
+        let min = component(&centroids.min, axis);
+        let extent = component(&centroids.max, axis) - min;
+        let offset = component(&primitive.centroid, axis) - min;
+        let bin = (offset / extent * SAH_BINS as f64) as usize;
+        bin.min(SAH_BINS - 1)
+    };
+
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        if component(&centroids.max, axis) <= component(&centroids.min, axis) {
+            continue;
+        }
+        let mut bins = [(BoundingBox::empty(), 0); SAH_BINS];
+        for primitive in primitives.iter() {
+            let bin = &mut bins[bin_of(primitive, axis)];
+            bin.0 = bin.0.union(&primitive.bounds);
+            bin.1 += 1;
+        }
+
+        for boundary in 1..SAH_BINS {
+            let (lower, upper) = bins.split_at(boundary);
+            let (lower_bounds, lower_count) = lower.iter()
+                .fold((BoundingBox::empty(), 0), |(b, n), bin| (b.union(&bin.0), n + bin.1));
+            let (upper_bounds, upper_count) = upper.iter()
+                .fold((BoundingBox::empty(), 0), |(b, n), bin| (b.union(&bin.0), n + bin.1));
+            if lower_count == 0 || upper_count == 0 {
+                continue;
+            }
+            let cost = lower_bounds.surface_area() * lower_count as f64 +
+                       upper_bounds.surface_area() * upper_count as f64;
+            if best.map_or(true, |(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, boundary));
+            }
+        }
+    }
+
+    //Every centroid is in the same place, so there's no way to separate them.
+    let (cost, axis, boundary) = match best {
+        Some(best) => best,
+        None => return None,
+    };
+    let area = bounds.surface_area();
+    let leaf_cost = primitives.len() as f64 * area;
+    if primitives.len() <= MAX_LEAF_SIZE && TRAVERSAL_COST * area + cost >= leaf_cost {
+        return None;
+    }
+
+    let mut middle = 0;
+    for i in 0..primitives.len() {
+        if bin_of(&primitives[i], axis) < boundary {
+            primitives.swap(i, middle);
+            middle += 1;
+        }
+    }
+    Some((axis, middle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rendering::Intersectable;
+    use scene::{Color, Coloration, Material, Sphere, SurfaceType};
+
+    /// A small xorshift generator, so the test sees the same "random" scene every time.
+    struct Random(u64);
+    impl Random {
+        fn next(&mut self) -> f64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn between(&mut self, low: f64, high: f64) -> f64 {
+            low + (high - low) * self.next()
+        }
+    }
+
+    fn sphere(random: &mut Random) -> Sphere {
+        Sphere {
+            center: Point {
+                x: random.between(-10.0, 10.0),
+                y: random.between(-10.0, 10.0),
+                z: random.between(-10.0, 10.0),
+            },
+            radius: random.between(0.05, 1.0),
+            material: Material {
+                coloration: Coloration::Color(Color {
+                    red: 1.0,
+                    green: 1.0,
+                    blue: 1.0,
+                }),
+                albedo: 0.18,
+                surface: SurfaceType::Diffuse,
+            },
+            velocity: Vector3::zero(),
+        }
+    }
+
+    #[test]
+    fn nearest_matches_linear_scan() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        let spheres: Vec<_> = (0..500).map(|_| sphere(&mut random)).collect();
+        let bounds: Vec<_> = spheres.iter().map(|s| s.bounding_box()).collect();
+        let bvh = Bvh::build(&bounds);
+        assert_eq!(bvh.len(), spheres.len());
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray {
+                origin: Point {
+                    x: random.between(-15.0, 15.0),
+                    y: random.between(-15.0, 15.0),
+                    z: random.between(-15.0, 15.0),
+                },
+                direction: Vector3 {
+                        x: random.between(-1.0, 1.0),
+                        y: random.between(-1.0, 1.0),
+                        z: random.between(-1.0, 1.0),
+                    }
+                    .normalize(),
+                time: 0.0,
+            };
+
+            let expected = spheres.iter()
+                .enumerate()
+                .filter_map(|(i, s)| s.intersect(&ray).map(|d| (d, i)))
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            let actual = bvh.nearest(&ray, |i| spheres[i].intersect(&ray).map(|d| (d, i)));
+            assert_eq!(actual, expected);
+            if actual.is_some() {
+                hits += 1;
+            }
+        }
+        //Make sure the rays weren't all misses, which would match trivially.
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn unbounded_primitives_are_always_tested() {
+        let bvh = Bvh::build(&[None, Some(BoundingBox::empty())]);
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            time: 0.0,
+        };
+        let mut tested = vec![];
+        bvh.nearest(&ray, |i| -> Option<(f64, ())> {
+            tested.push(i);
+            None
+        });
+        assert_eq!(tested, vec![0]);
+    }
+}
@@ -0,0 +1,176 @@
+use point::Point;
+use vector::Vector3;
+use matrix::Matrix44;
+
+/// Which extent of the image the camera's field of view spans.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub enum FovAxis {
+    Horizontal,
+    Vertical,
+    Diagonal,
+}
+impl Default for FovAxis {
+    fn default() -> FovAxis {
+        FovAxis::Vertical
+    }
+}
+
+/// How a fisheye lens maps the angle from its axis onto distance from the image center.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub enum FisheyeMapping {
+    Equidistant,
+    Equisolid,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum Projection {
+    Perspective,
+    /// Parallel projection; `view_width` is the horizontal extent of the view in world units.
+    Orthographic { view_width: f64 },
+    /// Full 360-degree panorama; the image's x axis maps to longitude and its y axis to
+    /// latitude.
+    Equirectangular,
+    /// Circular fisheye inscribed in the shorter image dimension, covering `fov` degrees (up to
+    /// 360) across its diameter.
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    /// Panorama wrapped around the camera's up axis, covering `fov` degrees horizontally.
+    Cylindrical { fov: f64 },
+}
+impl Default for Projection {
+    fn default() -> Projection {
+        Projection::Perspective
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Stereo {
+    /// Distance between the left and right eye positions.
+    pub interocular_distance: f64,
+    /// Distance in front of the camera at which the two eyes' images line up exactly.
+    pub convergence_distance: f64,
+}
+
+/// The interval of time over which the camera gathers light. Moving elements are blurred
+/// along their path over this interval.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+}
+impl Shutter {
+    /// Returns the time the given fraction of the way through the interval.
+    pub fn time(&self, fraction: f64) -> f64 {
+        self.open + (self.close - self.open) * fraction
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Camera {
+    pub position: Point,
+    pub look_at: Point,
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub up: Vector3,
+    #[serde(default)]
+    pub projection: Projection,
+    pub fov: f64,
+    #[serde(default)]
+    pub fov_axis: FovAxis,
+
+    /// Radius of the thin lens; zero gives a pinhole camera with everything in focus.
+    #[serde(default)]
+    pub aperture: f64,
+    /// Distance from the lens to the plane that is in perfect focus.
+    #[serde(default = "default_focal_distance")]
+    pub focal_distance: f64,
+
+    /// Horizontal offset of the image plane at unit distance, like a shift lens. Only affects
+    /// the perspective projection.
+    #[serde(default)]
+    pub lens_shift: f64,
+    #[serde(default)]
+    pub stereo: Option<Stereo>,
+
+    #[serde(default)]
+    pub shutter: Shutter,
+}
+fn default_focal_distance() -> f64 {
+    1.0
+}
+impl Default for Camera {
+    /// A camera at the origin looking down -Z with a 90 degree field of view.
+    fn default() -> Camera {
+        Camera {
+            position: Point::zero(),
+            look_at: Point {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            up: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            projection: Projection::default(),
+            fov: 90.0,
+            fov_axis: FovAxis::default(),
+            aperture: 0.0,
+            focal_distance: default_focal_distance(),
+            lens_shift: 0.0,
+            stereo: None,
+            shutter: Shutter::default(),
+        }
+    }
+}
+impl Camera {
+    pub(crate) fn camera_to_world(&self) -> Matrix44 {
+        Matrix44::look_at(self.position, self.look_at, self.up)
+    }
+
+    /// Returns the camera for one eye of a stereo pair. Both eyes look parallel to this camera
+    /// and use opposite lens shifts so that they converge at the convergence distance. A camera
+    /// without stereo settings is returned unchanged.
+    pub fn for_eye(&self, eye: Eye) -> Camera {
+        let mut camera = self.clone();
+        if let Some(ref stereo) = self.stereo {
+            let half_distance = match eye {
+                Eye::Left => -stereo.interocular_distance / 2.0,
+                Eye::Right => stereo.interocular_distance / 2.0,
+            };
+            let camera_to_world = self.camera_to_world();
+            let right = Vector3 {
+                x: camera_to_world[0][0],
+                y: camera_to_world[0][1],
+                z: camera_to_world[0][2],
+            };
+            camera.position = self.position + right * half_distance;
+            camera.look_at = self.look_at + right * half_distance;
+            camera.lens_shift = self.lens_shift - half_distance / stereo.convergence_distance;
+        }
+        camera
+    }
+
+    /// Returns the half-width and half-height of the image plane at unit distance from the
+    /// camera for an image of the given dimensions.
+    pub fn sensor_size(&self, width: u32, height: u32) -> (f64, f64) {
+        let fov_adjustment = (self.fov.to_radians() / 2.0).tan();
+        let width = width as f64;
+        let height = height as f64;
+        match self.fov_axis {
+            FovAxis::Horizontal => (fov_adjustment, fov_adjustment * height / width),
+            FovAxis::Vertical => (fov_adjustment * width / height, fov_adjustment),
+            FovAxis::Diagonal => {
+                let diagonal = (width * width + height * height).sqrt();
+                (fov_adjustment * width / diagonal, fov_adjustment * height / diagonal)
+            }
+        }
+    }
+}
@@ -0,0 +1,1148 @@
+//! Import of glTF 2.0 files (`.gltf` with external or embedded buffers, and binary `.glb`).
+
+use point::Point;
+use vector::Vector3;
+use matrix::Matrix44;
+use camera::{Camera, FovAxis, Projection, Shutter};
+use mesh::{Face, Mesh, MeshData};
+use bvh::Bvh;
+use scene::{Color, Coloration, DirectionalLight, Element, Light, Material, Scene,
+            SphericalLight, SurfaceType, Texture, TextureCoords};
+use std::collections::{BTreeMap, HashMap};
+use std::f64;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use image;
+use image::DynamicImage;
+use serde_json;
+
+#[derive(Deserialize, Debug, Default)]
+struct Document {
+    #[serde(default)]
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<DocumentScene>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<DocumentMesh>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default, rename = "bufferViews")]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default)]
+    materials: Vec<DocumentMaterial>,
+    #[serde(default)]
+    textures: Vec<DocumentTexture>,
+    #[serde(default)]
+    images: Vec<DocumentImage>,
+    #[serde(default)]
+    cameras: Vec<DocumentCamera>,
+    #[serde(default)]
+    extensions: DocumentExtensions,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct DocumentScene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Node {
+    #[serde(default)]
+    children: Vec<usize>,
+    #[serde(default)]
+    mesh: Option<usize>,
+    #[serde(default)]
+    camera: Option<usize>,
+    #[serde(default)]
+    matrix: Option<Vec<f64>>,
+    #[serde(default)]
+    translation: Option<Vec<f64>>,
+    #[serde(default)]
+    rotation: Option<Vec<f64>>,
+    #[serde(default)]
+    scale: Option<Vec<f64>>,
+    #[serde(default)]
+    extensions: NodeExtensions,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct NodeExtensions {
+    #[serde(default, rename = "KHR_lights_punctual")]
+    lights_punctual: Option<NodeLight>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NodeLight {
+    light: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct DocumentMesh {
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    #[serde(default)]
+    indices: Option<usize>,
+    #[serde(default)]
+    material: Option<usize>,
+    #[serde(default)]
+    mode: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Accessor {
+    #[serde(default, rename = "bufferView")]
+    buffer_view: Option<usize>,
+    #[serde(default, rename = "byteOffset")]
+    byte_offset: usize,
+    #[serde(rename = "componentType")]
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BufferView {
+    buffer: usize,
+    #[serde(default, rename = "byteOffset")]
+    byte_offset: usize,
+    #[serde(rename = "byteLength")]
+    byte_length: usize,
+    #[serde(default, rename = "byteStride")]
+    byte_stride: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Buffer {
+    #[serde(default)]
+    uri: Option<String>,
+    #[serde(rename = "byteLength")]
+    byte_length: usize,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct DocumentMaterial {
+    #[serde(default, rename = "pbrMetallicRoughness")]
+    pbr: Option<PbrMetallicRoughness>,
+    #[serde(default, rename = "alphaMode")]
+    alpha_mode: Option<String>,
+    #[serde(default, rename = "doubleSided")]
+    double_sided: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct PbrMetallicRoughness {
+    #[serde(default, rename = "baseColorFactor")]
+    base_color_factor: Option<Vec<f32>>,
+    #[serde(default, rename = "baseColorTexture")]
+    base_color_texture: Option<TextureInfo>,
+    #[serde(default, rename = "metallicFactor")]
+    metallic_factor: Option<f32>,
+    #[serde(default, rename = "roughnessFactor")]
+    roughness_factor: Option<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TextureInfo {
+    index: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct DocumentTexture {
+    #[serde(default)]
+    source: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DocumentImage {
+    #[serde(default)]
+    uri: Option<String>,
+    #[serde(default, rename = "bufferView")]
+    buffer_view: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DocumentCamera {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    perspective: Option<PerspectiveCamera>,
+    #[serde(default)]
+    orthographic: Option<OrthographicCamera>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PerspectiveCamera {
+    yfov: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct OrthographicCamera {
+    xmag: f64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct DocumentExtensions {
+    #[serde(default, rename = "KHR_lights_punctual")]
+    lights_punctual: Option<LightsPunctual>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LightsPunctual {
+    lights: Vec<PunctualLight>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PunctualLight {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    color: Option<Vec<f32>>,
+    #[serde(default)]
+    intensity: Option<f32>,
+}
+
+/// A parsed glTF file along with the contents of all of its buffers.
+struct Gltf {
+    path: PathBuf,
+    document: Document,
+    buffers: Vec<Vec<u8>>,
+}
+
+/// Everything placed in the world by walking the node hierarchy.
+#[derive(Default)]
+struct Placements {
+    meshes: Vec<(usize, Matrix44)>,
+    cameras: Vec<(usize, Matrix44)>,
+    lights: Vec<(usize, Matrix44)>,
+}
+
+const GLB_MAGIC: &'static [u8] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4E4F534A;
+const GLB_BIN_CHUNK: u32 = 0x004E4942;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut accumulator = 0u32;
+    let mut bits = 0;
+    for byte in encoded.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b'\r' | b'\n' | b' ' => continue,
+            _ => return Err(format!("invalid base64 character {:?}", byte as char)),
+        };
+        accumulator = (accumulator << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((accumulator >> bits) as u8);
+            accumulator &= (1 << bits) - 1;
+        }
+    }
+    Ok(decoded)
+}
+
+impl Gltf {
+    fn open(path: &Path) -> Result<Gltf, String> {
+        let mut bytes = vec![];
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| format!("Unable to open glTF file {:?}: {}", path, e))?;
+        Gltf::parse(path, &bytes).map_err(|e| format!("Invalid glTF file {:?}: {}", path, e))
+    }
+
+    fn parse(path: &Path, bytes: &[u8]) -> Result<Gltf, String> {
+        let (json, binary_chunk) = if bytes.starts_with(GLB_MAGIC) {
+            Gltf::split_glb(bytes)?
+        } else {
+            (bytes, None)
+        };
+        let document: Document = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+
+        let mut buffers = vec![];
+        for (index, buffer) in document.buffers.iter().enumerate() {
+            let data = match buffer.uri {
+                Some(ref uri) => Gltf::read_uri(path, uri)?,
+                None if index == 0 && binary_chunk.is_some() => binary_chunk.unwrap().to_vec(),
+                None => return Err(format!("buffer {} has no data", index)),
+            };
+            if data.len() < buffer.byte_length {
+                return Err(format!("buffer {} is shorter than its byteLength", index));
+            }
+            buffers.push(data);
+        }
+
+        Ok(Gltf {
+            path: path.to_path_buf(),
+            document: document,
+            buffers: buffers,
+        })
+    }
+
+    fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+        if bytes.len() < 20 || read_u32(bytes, 4) != 2 {
+            return Err("unsupported GLB header".to_string());
+        }
+        let mut json = None;
+        let mut binary = None;
+        let mut offset = 12;
+        while offset + 8 <= bytes.len() {
+            let length = read_u32(bytes, offset) as usize;
+            let kind = read_u32(bytes, offset + 4);
+            let start = offset + 8;
+            if start + length > bytes.len() {
+                return Err("GLB chunk runs past the end of the file".to_string());
+            }
+            match kind {
+                GLB_JSON_CHUNK => json = Some(&bytes[start..start + length]),
+                GLB_BIN_CHUNK => binary = Some(&bytes[start..start + length]),
+                _ => {}
+            }
+            offset = start + length;
+        }
+        json.map(|j| (j, binary)).ok_or_else(|| "GLB file has no JSON chunk".to_string())
+    }
+
+    fn read_uri(path: &Path, uri: &str) -> Result<Vec<u8>, String> {
+        if uri.starts_with("data:") {
+            let comma = uri.find(',').ok_or("malformed data URI")?;
+            if !uri[..comma].ends_with(";base64") {
+                return Err("only base64 data URIs are supported".to_string());
+            }
+            return decode_base64(&uri[comma + 1..]);
+        }
+        let file_path = path.with_file_name(uri.replace("%20", " "));
+        let mut data = vec![];
+        File::open(&file_path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| format!("unable to open {:?}: {}", file_path, e))?;
+        Ok(data)
+    }
+
+    fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>), String> {
+        let view = self.document
+            .buffer_views
+            .get(index)
+            .ok_or_else(|| format!("missing buffer view {}", index))?;
+        let buffer = self.buffers
+            .get(view.buffer)
+            .ok_or_else(|| format!("missing buffer {}", view.buffer))?;
+        let end = match view.byte_offset.checked_add(view.byte_length) {
+            Some(end) if end <= buffer.len() => end,
+            _ => return Err(format!("buffer view {} runs past the end of its buffer", index)),
+        };
+        Ok((&buffer[view.byte_offset..end], view.byte_stride))
+    }
+
+    /// Reads every element of an accessor, converting each component to f64.
+    fn read_accessor(&self, index: usize) -> Result<Vec<Vec<f64>>, String> {
+        let accessor = self.document
+            .accessors
+            .get(index)
+            .ok_or_else(|| format!("missing accessor {}", index))?;
+        let components = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            "MAT4" => 16,
+            other => return Err(format!("unsupported accessor type {:?}", other)),
+        };
+        let (component_size, normalization) = match accessor.component_type {
+            5120 => (1, 127.0),
+            5121 => (1, 255.0),
+            5122 => (2, 32767.0),
+            5123 => (2, 65535.0),
+            5125 => (4, 4294967295.0),
+            5126 => (4, 1.0),
+            other => return Err(format!("unsupported component type {}", other)),
+        };
+        let buffer_view = match accessor.buffer_view {
+            Some(view) => view,
+            None => return Ok(vec![vec![0.0; components]; accessor.count]),
+        };
+        let (data, stride) = self.buffer_view(buffer_view)?;
+        let element_size = components * component_size;
+        let stride = stride.unwrap_or(element_size);
+        if stride < element_size {
+            return Err(format!("accessor {} has elements wider than its stride", index));
+        }
+        if accessor.count > 0 {
+            //The counts and offsets come straight from the file, so guard against overflow.
+            let end = stride.checked_mul(accessor.count - 1)
+                .and_then(|offset| offset.checked_add(accessor.byte_offset))
+                .and_then(|offset| offset.checked_add(element_size));
+            match end {
+                Some(end) if end <= data.len() => {}
+                _ => return Err(format!("accessor {} runs past the end of its buffer view", index)),
+            }
+        }
+
+        let mut elements = Vec::with_capacity(accessor.count);
+        for i in 0..accessor.count {
+            let start = accessor.byte_offset + i * stride;
+            let element = (0..components)
+                .map(|c| {
+                    let b = &data[start + c * component_size..];
+                    let value = match accessor.component_type {
+                        5120 => b[0] as i8 as f64,
+                        5121 => b[0] as f64,
+                        5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                        5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                        5125 => read_u32(b, 0) as f64,
+                        _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    };
+                    if accessor.normalized {
+                        (value / normalization).max(-1.0)
+                    } else {
+                        value
+                    }
+                })
+                .collect();
+            elements.push(element);
+        }
+        Ok(elements)
+    }
+
+    fn local_transform(node: &Node) -> Matrix44 {
+        if let Some(ref m) = node.matrix {
+            if m.len() == 16 {
+                //glTF matrices are column-major and multiply column vectors, which lays them
+                //out exactly like our row-vector matrices.
+                return Matrix44::new([[m[0], m[1], m[2], m[3]],
+                                      [m[4], m[5], m[6], m[7]],
+                                      [m[8], m[9], m[10], m[11]],
+                                      [m[12], m[13], m[14], m[15]]]);
+            }
+        }
+
+        let mut transform = Matrix44::identity();
+        if let Some(ref s) = node.scale {
+            if s.len() == 3 {
+                transform = Matrix44::scale(s[0], s[1], s[2]);
+            }
+        }
+        if let Some(ref r) = node.rotation {
+            if r.len() == 4 {
+                transform = transform * Matrix44::rotate_quaternion(r[0], r[1], r[2], r[3]);
+            }
+        }
+        if let Some(ref t) = node.translation {
+            if t.len() == 3 {
+                transform[3][0] += t[0];
+                transform[3][1] += t[1];
+                transform[3][2] += t[2];
+            }
+        }
+        transform
+    }
+
+    /// Walks the node hierarchy of the default scene, recording the world transform of every
+    /// mesh, camera and light.
+    fn placements(&self) -> Result<Placements, String> {
+        let document = &self.document;
+        let roots = match document.scenes.get(document.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            None => {
+                let children: Vec<usize> =
+                    document.nodes.iter().flat_map(|n| n.children.iter().cloned()).collect();
+                (0..document.nodes.len()).filter(|n| !children.contains(n)).collect()
+            }
+        };
+
+        let mut placements = Placements::default();
+        //The stack is popped from the end, so push everything in reverse to visit it in order.
+        let mut stack: Vec<(usize, Matrix44, usize)> =
+            roots.into_iter().rev().map(|n| (n, Matrix44::identity(), 0)).collect();
+        while let Some((index, parent, depth)) = stack.pop() {
+            if depth > document.nodes.len() {
+                return Err("node hierarchy contains a cycle".to_string());
+            }
+            let node = document.nodes.get(index).ok_or_else(|| format!("missing node {}", index))?;
+            let world = Gltf::local_transform(node) * parent;
+            if let Some(mesh) = node.mesh {
+                placements.meshes.push((mesh, world));
+            }
+            if let Some(camera) = node.camera {
+                placements.cameras.push((camera, world));
+            }
+            if let Some(ref light) = node.extensions.lights_punctual {
+                placements.lights.push((light.light, world));
+            }
+            for &child in node.children.iter().rev() {
+                stack.push((child, world, depth + 1));
+            }
+        }
+        Ok(placements)
+    }
+
+    fn load_image(&self, index: usize) -> Result<Texture, String> {
+        let image = self.document
+            .images
+            .get(index)
+            .ok_or_else(|| format!("missing image {}", index))?;
+        let (path, bytes) = match (image.uri.as_ref(), image.buffer_view) {
+            (Some(uri), _) if !uri.starts_with("data:") => {
+                let path = self.path.with_file_name(uri.replace("%20", " "));
+                (path.clone(), Gltf::read_uri(&self.path, uri)?)
+            }
+            (Some(uri), _) => (self.path.clone(), Gltf::read_uri(&self.path, uri)?),
+            (None, Some(view)) => (self.path.clone(), self.buffer_view(view)?.0.to_vec()),
+            (None, None) => return Err(format!("image {} has no data", index)),
+        };
+        let texture = image::load_from_memory(&bytes)
+            .map_err(|e| format!("unable to decode image {}: {}", index, e))?;
+        Ok(Texture {
+            path: path,
+            texture: texture,
+        })
+    }
+
+    fn material(&self, index: usize, base_material: &Material) -> Result<Material, String> {
+        let material = self.document
+            .materials
+            .get(index)
+            .ok_or_else(|| format!("missing material {}", index))?;
+        let pbr = match material.pbr {
+            Some(ref pbr) => pbr,
+            None => {
+                return Ok(Material {
+                    coloration: Coloration::Color(Color {
+                        red: 1.0,
+                        green: 1.0,
+                        blue: 1.0,
+                    }),
+                    albedo: base_material.albedo,
+                    surface: base_material.surface.clone(),
+                })
+            }
+        };
+
+        let factor = pbr.base_color_factor.clone().unwrap_or_else(|| vec![1.0; 4]);
+        let alpha = factor.get(3).cloned().unwrap_or(1.0);
+        let base_color = Color {
+            red: factor.get(0).cloned().unwrap_or(1.0),
+            green: factor.get(1).cloned().unwrap_or(1.0),
+            blue: factor.get(2).cloned().unwrap_or(1.0),
+        };
+        let texture = pbr.base_color_texture
+            .as_ref()
+            .and_then(|t| self.document.textures.get(t.index))
+            .and_then(|t| t.source);
+        let coloration = match texture {
+            Some(image) => {
+                //The texture is scaled by the base color factor.
+                let mut texture = self.load_image(image)?;
+                if base_color.red != 1.0 || base_color.green != 1.0 || base_color.blue != 1.0 {
+                    texture.texture = tint(&texture.texture, base_color);
+                }
+                Coloration::Texture(texture)
+            }
+            None => Coloration::Color(base_color),
+        };
+
+        //Approximate the metallic-roughness model with the surfaces we support: smooth metals
+        //become mirrors and blended transparent materials become glass.
+        let metallic = pbr.metallic_factor.unwrap_or(1.0);
+        let roughness = pbr.roughness_factor.unwrap_or(1.0);
+        let reflectivity = metallic * (1.0 - roughness);
+        let surface = if material.alpha_mode.as_ref().map(|m| m.as_str()) == Some("BLEND") &&
+                         alpha < 1.0 {
+            SurfaceType::Refractive {
+                index: 1.5,
+                transparency: 1.0 - alpha,
+            }
+        } else if reflectivity > 0.0 {
+            SurfaceType::Reflective { reflectivity: reflectivity }
+        } else {
+            SurfaceType::Diffuse
+        };
+
+        Ok(Material {
+            coloration: coloration,
+            albedo: base_material.albedo,
+            surface: surface,
+        })
+    }
+
+    /// Appends a primitive's triangles to the mesh data, moved into world space by `transform`
+    /// and using the given index into the data's materials. Primitives that aren't triangle
+    /// lists are skipped, since points, lines and strips aren't supported.
+    fn add_primitive(&self,
+                     data: &mut MeshData,
+                     mesh_index: usize,
+                     primitive: &Primitive,
+                     transform: Matrix44,
+                     material: Option<usize>)
+                     -> Result<(), String> {
+        if primitive.mode.unwrap_or(4) != 4 {
+            return Ok(());
+        }
+        let positions = match primitive.attributes.get("POSITION") {
+            Some(&accessor) => self.read_accessor(accessor)?,
+            None => return Ok(()),
+        };
+        let read_vertex_attribute = |name: &str| -> Result<Option<Vec<Vec<f64>>>, String> {
+            let accessor = match primitive.attributes.get(name) {
+                Some(&accessor) => accessor,
+                None => return Ok(None),
+            };
+            let values = self.read_accessor(accessor)?;
+            if values.len() != positions.len() {
+                return Err(format!("mesh {} has {} {} values for {} positions",
+                                   mesh_index,
+                                   values.len(),
+                                   name,
+                                   positions.len()));
+            }
+            Ok(Some(values))
+        };
+        let normals = read_vertex_attribute("NORMAL")?;
+        let texture_coords = read_vertex_attribute("TEXCOORD_0")?;
+        let indices: Vec<usize> = match primitive.indices {
+            Some(accessor) => self.read_accessor(accessor)?.iter().map(|i| i[0] as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+
+        let normal_transform = transform.inverse().transpose();
+        let base = data.positions.len();
+        let normal_base = data.normals.len();
+        let coords_base = data.texture_coords.len();
+        for p in &positions {
+            data.positions.push(transform *
+                                Point {
+                x: p[0],
+                y: p[1],
+                z: p[2],
+            });
+        }
+        if let Some(ref normals) = normals {
+            for n in normals {
+                data.normals.push((normal_transform *
+                                   Vector3 {
+                        x: n[0],
+                        y: n[1],
+                        z: n[2],
+                    })
+                    .normalize());
+            }
+        }
+        if let Some(ref coords) = texture_coords {
+            for t in coords {
+                data.texture_coords.push(TextureCoords {
+                    x: t[0] as f32,
+                    y: t[1] as f32,
+                });
+            }
+        }
+
+        for triangle in indices.chunks(3).filter(|t| t.len() == 3) {
+            if triangle.iter().any(|&i| i >= positions.len()) {
+                return Err(format!("mesh {} refers to a missing vertex", mesh_index));
+            }
+            let corners = [triangle[0], triangle[1], triangle[2]];
+            data.faces.push(Face {
+                vertices: [base + corners[0], base + corners[1], base + corners[2]],
+                normals: normals.as_ref().map(|_| {
+                    [normal_base + corners[0], normal_base + corners[1], normal_base + corners[2]]
+                }),
+                texture_coords: texture_coords.as_ref().map(|_| {
+                    [coords_base + corners[0], coords_base + corners[1], coords_base + corners[2]]
+                }),
+                material: material,
+            });
+        }
+        Ok(())
+    }
+
+    fn mesh(&self, index: usize) -> Result<&DocumentMesh, String> {
+        self.document.meshes.get(index).ok_or_else(|| format!("missing mesh {}", index))
+    }
+
+    /// Merges every triangle primitive placed in the scene into one mesh in world space.
+    fn mesh_data(&self,
+                 placements: &Placements,
+                 base_material: &Material)
+                 -> Result<MeshData, String> {
+        let mut data = MeshData::default();
+        let mut materials = HashMap::new();
+        for &(mesh_index, transform) in &placements.meshes {
+            for primitive in &self.mesh(mesh_index)?.primitives {
+                let material = match primitive.material {
+                    Some(index) => {
+                        if !materials.contains_key(&index) {
+                            materials.insert(index, data.materials.len());
+                            let material = self.material(index, base_material)?;
+                            data.materials.push(material);
+                        }
+                        Some(materials[&index])
+                    }
+                    None => None,
+                };
+                self.add_primitive(&mut data, mesh_index, primitive, transform, material)?;
+            }
+        }
+        data.compute_smooth_normals();
+        Ok(data)
+    }
+
+    /// Makes a mesh in world space out of every triangle primitive placed in the scene, each
+    /// with its own material and sidedness.
+    fn meshes(&self,
+              placements: &Placements,
+              base_material: &Material)
+              -> Result<Vec<Mesh>, String> {
+        let mut meshes = vec![];
+        let mut materials = HashMap::new();
+        for &(mesh_index, transform) in &placements.meshes {
+            for primitive in &self.mesh(mesh_index)?.primitives {
+                let mut data = MeshData::default();
+                self.add_primitive(&mut data, mesh_index, primitive, transform, None)?;
+                if data.faces.is_empty() {
+                    continue;
+                }
+                data.compute_smooth_normals();
+
+                let (material, double_sided) = match primitive.material {
+                    Some(index) => {
+                        if !materials.contains_key(&index) {
+                            let material = self.material(index, base_material)?;
+                            materials.insert(index, material);
+                        }
+                        (materials[&index].clone(), self.document.materials[index].double_sided)
+                    }
+                    None => (base_material.clone(), false),
+                };
+                meshes.push(Mesh::new(self.path.clone(), material, double_sided, data));
+            }
+        }
+        Ok(meshes)
+    }
+
+    fn camera(&self, index: usize, transform: Matrix44) -> Result<Camera, String> {
+        let camera = self.document
+            .cameras
+            .get(index)
+            .ok_or_else(|| format!("missing camera {}", index))?;
+        let position = transform * Point::zero();
+        let forward = transform *
+                      Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
+        };
+        let up = transform *
+                 Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+
+        let (projection, fov) = match (camera.kind.as_str(),
+                                       camera.perspective.as_ref(),
+                                       camera.orthographic.as_ref()) {
+            ("perspective", Some(p), _) => (Projection::Perspective, p.yfov.to_degrees()),
+            ("orthographic", _, Some(o)) => {
+                (Projection::Orthographic { view_width: o.xmag * 2.0 }, 90.0)
+            }
+            _ => return Err(format!("camera {} has an unsupported type", index)),
+        };
+        Ok(Camera {
+            position: position,
+            look_at: position + forward,
+            up: up.normalize(),
+            projection: projection,
+            fov: fov,
+            fov_axis: FovAxis::Vertical,
+            aperture: 0.0,
+            focal_distance: 1.0,
+            lens_shift: 0.0,
+            stereo: None,
+            shutter: Shutter::default(),
+        })
+    }
+
+    fn light(&self, index: usize, transform: Matrix44) -> Result<Light, String> {
+        let light = self.document
+            .extensions
+            .lights_punctual
+            .as_ref()
+            .and_then(|l| l.lights.get(index))
+            .ok_or_else(|| format!("missing light {}", index))?;
+        let c = light.color.clone().unwrap_or_else(|| vec![1.0; 3]);
+        let color = Color {
+            red: c.get(0).cloned().unwrap_or(1.0),
+            green: c.get(1).cloned().unwrap_or(1.0),
+            blue: c.get(2).cloned().unwrap_or(1.0),
+        };
+        let intensity = light.intensity.unwrap_or(1.0);
+
+        //Spot lights are treated as point lights, since there is no cone falloff here.
+        match light.kind.as_str() {
+            "directional" => {
+                let direction = transform *
+                                Vector3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: -1.0,
+                };
+                Ok(Light::Directional(DirectionalLight {
+                    direction: direction.normalize(),
+                    color: color,
+                    intensity: intensity,
+                }))
+            }
+            "point" | "spot" => {
+                //glTF gives point light intensity in candela, which is per steradian.
+                Ok(Light::Spherical(SphericalLight {
+                    position: transform * Point::zero(),
+                    color: color,
+                    intensity: intensity * 4.0 * ::std::f32::consts::PI,
+                }))
+            }
+            other => Err(format!("unsupported light type {:?}", other)),
+        }
+    }
+}
+
+/// Multiplies every pixel of an image by a color, keeping its alpha.
+fn tint(image: &DynamicImage, color: Color) -> DynamicImage {
+    let mut pixels = image.to_rgba();
+    for pixel in pixels.pixels_mut() {
+        let alpha = pixel.data[3];
+        *pixel = (Color::from_rgba(*pixel) * color).to_rgba();
+        pixel.data[3] = alpha;
+    }
+    DynamicImage::ImageRgba8(pixels)
+}
+
+fn default_material() -> Material {
+    Material {
+        coloration: Coloration::Color(Color {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        }),
+        albedo: 0.18,
+        surface: SurfaceType::Diffuse,
+    }
+}
+
+/// Loads every mesh placed in a glTF file's default scene as a single mesh in world space.
+pub fn load_mesh_data(path: &Path, base_material: &Material) -> Result<MeshData, String> {
+    let gltf = Gltf::open(path)?;
+    let placements = gltf.placements()?;
+    gltf.mesh_data(&placements, base_material)
+        .map_err(|e| format!("Invalid glTF file {:?}: {}", path, e))
+}
+
+/// Builds a scene from a glTF file's default scene: each of its mesh primitives becomes a `Mesh`
+/// element with its own material, and its first camera and its punctual lights are used when
+/// present. Without a camera the scene is framed from the front; without lights a white
+/// directional light is added.
+pub fn load_scene(path: &Path, width: u32, height: u32) -> Result<Scene, String> {
+    let gltf = Gltf::open(path)?;
+    build_scene(&gltf, width, height).map_err(|e| format!("Invalid glTF file {:?}: {}", path, e))
+}
+
+fn build_scene(gltf: &Gltf, width: u32, height: u32) -> Result<Scene, String> {
+    let placements = gltf.placements()?;
+
+    let base_material = default_material();
+    let meshes = gltf.meshes(&placements, &base_material)?;
+
+    let camera = match placements.cameras.first() {
+        Some(&(index, transform)) => gltf.camera(index, transform)?,
+        None => framing_camera(&meshes),
+    };
+
+    let mut lights = vec![];
+    for &(index, transform) in &placements.lights {
+        lights.push(gltf.light(index, transform)?);
+    }
+    if lights.is_empty() {
+        lights.push(Light::Directional(DirectionalLight {
+            direction: Vector3 {
+                    x: -1.0,
+                    y: -1.0,
+                    z: -1.0,
+                }
+                .normalize(),
+            color: Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 5.0,
+        }));
+    }
+
+    let mut scene = Scene {
+        width: width,
+        height: height,
+        camera: camera,
+        definitions: BTreeMap::new(),
+        elements: meshes.into_iter().map(Element::Mesh).collect(),
+        lights: lights,
+        shadow_bias: 1e-6,
+        max_recursion_depth: 10,
+        samples_per_pixel: 1,
+        adaptive_sampling: None,
+        animation: vec![],
+        bvh: Bvh::default(),
+    };
+    scene.build_bvh();
+    Ok(scene)
+}
+
+/// Places a camera on the +Z side of the meshes, far enough back to see all of them.
+fn framing_camera(meshes: &[Mesh]) -> Camera {
+    let positions: Vec<Point> =
+        meshes.iter().flat_map(|m| m.data.positions.iter().cloned()).collect();
+    let count = positions.len().max(1) as f64;
+    let center = Point::zero() +
+                 positions.iter().fold(Vector3::zero(), |sum, p| sum + (*p - Point::zero())) *
+                 (1.0 / count);
+    let radius = positions
+        .iter()
+        .map(|p| (*p - center).length())
+        .fold(0.0, f64::max)
+        .max(1e-3);
+    let fov: f64 = 60.0;
+    let distance = radius / (fov.to_radians() / 2.0).sin();
+    Camera {
+        position: center +
+                  Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: distance,
+        },
+        look_at: center,
+        up: Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        },
+        projection: Projection::Perspective,
+        fov: fov,
+        fov_axis: FovAxis::Vertical,
+        aperture: 0.0,
+        focal_distance: 1.0,
+        lens_shift: 0.0,
+        stereo: None,
+        shutter: Shutter::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_base64(bytes: &[u8]) -> String {
+        const ALPHABET: &'static [u8] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut encoded = String::new();
+        for chunk in bytes.chunks(3) {
+            let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &b)| {
+                bits | (b as u32) << (16 - 8 * i)
+            });
+            for i in 0..(chunk.len() + 1) {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        encoded
+    }
+
+    fn floats(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    }
+
+    fn buffer(data: &[u8], byte_length: usize) -> String {
+        format!(r#"{{"byteLength": {},
+                    "uri": "data:application/octet-stream;base64,{}"}}"#,
+                byte_length,
+                encode_base64(data))
+    }
+
+    fn parse(json: &str) -> Result<Gltf, String> {
+        Gltf::parse(Path::new("test.gltf"), json.as_bytes())
+    }
+
+    /// A document holding one triangle, read through the given accessor and buffer view.
+    fn triangle(accessor: &str, buffer_view: &str) -> String {
+        let positions = floats(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        format!(r#"{{"nodes": [{{"mesh": 0}}],
+                    "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}],
+                    "accessors": [{}],
+                    "bufferViews": [{}],
+                    "buffers": [{}]}}"#,
+                accessor,
+                buffer_view,
+                buffer(&positions, positions.len()))
+    }
+
+    fn assert_point(actual: Point, expected: [f64; 3]) {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+        assert!(close(actual.x, expected[0]) && close(actual.y, expected[1]) &&
+                close(actual.z, expected[2]),
+                "expected {:?}, got {:?}",
+                expected,
+                actual);
+    }
+
+    #[test]
+    fn decodes_base64() {
+        for length in 0..7 {
+            let bytes: Vec<u8> = (0..length).map(|i| (i * 97 + 13) as u8).collect();
+            assert_eq!(decode_base64(&encode_base64(&bytes)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn node_transforms_compose_from_parent_to_child() {
+        let positions = floats(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        let json = format!(r#"{{"scene": 0,
+                               "scenes": [{{"nodes": [0]}}],
+                               "nodes": [{{"translation": [0, 0, -5], "children": [1]}},
+                                         {{"rotation": [0, 0.7071067811865476,
+                                                        0, 0.7071067811865476],
+                                           "scale": [2, 2, 2],
+                                           "mesh": 0}}],
+                               "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}],
+                               "accessors": [{{"bufferView": 0, "componentType": 5126,
+                                               "count": 3, "type": "VEC3"}}],
+                               "bufferViews": [{{"buffer": 0, "byteLength": 36}}],
+                               "buffers": [{}]}}"#,
+                           buffer(&positions, positions.len()));
+        let scene = build_scene(&parse(&json).unwrap(), 4, 3).unwrap();
+        assert_eq!(scene.elements.len(), 1);
+        let positions = match scene.elements[0] {
+            Element::Mesh(ref mesh) => &mesh.data.positions,
+            _ => panic!("expected a mesh"),
+        };
+        //Each corner is scaled, then turned a quarter around +Y, then moved by the parent.
+        assert_point(positions[0], [0.0, 0.0, -7.0]);
+        assert_point(positions[1], [0.0, 2.0, -5.0]);
+        assert_point(positions[2], [2.0, 0.0, -5.0]);
+    }
+
+    #[test]
+    fn reads_interleaved_and_offset_accessors() {
+        //Two vertices of interleaved position and normal, then two normalized byte values.
+        let mut data = floats(&[1.0, 2.0, 3.0, 0.0, 0.0, 1.0, 4.0, 5.0, 6.0, 0.0, 1.0, 0.0]);
+        data.extend_from_slice(&[0, 0, 255, 51]);
+        let json = format!(r#"{{"accessors": [{{"bufferView": 0, "componentType": 5126,
+                                                "count": 2, "type": "VEC3"}},
+                                               {{"bufferView": 0, "byteOffset": 12,
+                                                "componentType": 5126, "count": 2,
+                                                "type": "VEC3"}},
+                                               {{"bufferView": 1, "byteOffset": 2,
+                                                "componentType": 5121, "normalized": true,
+                                                "count": 2, "type": "SCALAR"}}],
+                               "bufferViews": [{{"buffer": 0, "byteLength": 48,
+                                                 "byteStride": 24}},
+                                               {{"buffer": 0, "byteOffset": 48,
+                                                 "byteLength": 4}}],
+                               "buffers": [{}]}}"#,
+                           buffer(&data, data.len()));
+        let gltf = parse(&json).unwrap();
+        assert_eq!(gltf.read_accessor(0).unwrap(),
+                   vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+        assert_eq!(gltf.read_accessor(1).unwrap(),
+                   vec![vec![0.0, 0.0, 1.0], vec![0.0, 1.0, 0.0]]);
+        assert_eq!(gltf.read_accessor(2).unwrap(), vec![vec![1.0], vec![0.2]]);
+    }
+
+    #[test]
+    fn imports_cameras_and_lights() {
+        let json = r#"{"nodes": [{"camera": 0, "translation": [0, 1, 5]},
+                                 {"extensions": {"KHR_lights_punctual": {"light": 0}},
+                                  "translation": [1, 2, 3]},
+                                 {"extensions": {"KHR_lights_punctual": {"light": 1}},
+                                  "rotation": [-0.7071067811865476, 0, 0,
+                                               0.7071067811865476]}],
+                       "cameras": [{"type": "perspective",
+                                    "perspective": {"yfov": 0.5, "znear": 0.1}}],
+                       "extensions": {"KHR_lights_punctual": {"lights": [
+                           {"type": "point", "intensity": 10, "color": [1, 0.5, 0]},
+                           {"type": "directional"}]}}}"#;
+        let scene = build_scene(&parse(json).unwrap(), 4, 3).unwrap();
+
+        assert_point(scene.camera.position, [0.0, 1.0, 5.0]);
+        assert_point(scene.camera.look_at, [0.0, 1.0, 4.0]);
+        assert!((scene.camera.fov - 0.5f64.to_degrees()).abs() < 1e-9);
+
+        assert_eq!(scene.lights.len(), 2);
+        match scene.lights[0] {
+            Light::Spherical(ref light) => {
+                assert_point(light.position, [1.0, 2.0, 3.0]);
+                assert_eq!(light.color.green, 0.5);
+                assert!((light.intensity - 40.0 * ::std::f32::consts::PI).abs() < 1e-3);
+            }
+            _ => panic!("expected a point light"),
+        }
+        match scene.lights[1] {
+            //Turned a quarter around -X, so shining down instead of along -Z.
+            Light::Directional(ref light) => {
+                assert_point(Point::zero() + light.direction, [0.0, -1.0, 0.0])
+            }
+            _ => panic!("expected a directional light"),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_buffers() {
+        let data = floats(&[1.0, 0.0, 0.0]);
+        let json = format!(r#"{{"buffers": [{}]}}"#, buffer(&data, 36));
+        assert!(parse(&json).is_err());
+    }
+
+    #[test]
+    fn rejects_accessors_outside_their_buffer_view() {
+        let accessor = |count: &str, offset: &str| {
+            format!(r#"{{"bufferView": 0, "byteOffset": {}, "componentType": 5126,
+                         "count": {}, "type": "VEC3"}}"#,
+                    offset,
+                    count)
+        };
+        let view = r#"{"buffer": 0, "byteLength": 36}"#;
+        for &(count, offset) in &[("4", "0"), ("3", "4"), ("4611686018427387904", "0"),
+                                  ("2", "18446744073709551615")] {
+            let gltf = parse(&triangle(&accessor(count, offset), view)).unwrap();
+            assert!(build_scene(&gltf, 4, 3).is_err(),
+                    "count {} at offset {} was accepted",
+                    count,
+                    offset);
+        }
+
+        let accessor = accessor("3", "0");
+        for view in &[r#"{"buffer": 0, "byteLength": 40}"#,
+                      r#"{"buffer": 0, "byteOffset": 18446744073709551615, "byteLength": 2}"#,
+                      r#"{"buffer": 0, "byteLength": 36, "byteStride": 4}"#,
+                      r#"{"buffer": 1, "byteLength": 36}"#] {
+            let gltf = parse(&triangle(&accessor, view)).unwrap();
+            assert!(build_scene(&gltf, 4, 3).is_err(), "buffer view {} was accepted", view);
+        }
+    }
+}
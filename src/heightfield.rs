@@ -0,0 +1,144 @@
+use point::Point;
+use vector::Vector3;
+use scene::Material;
+use image;
+use image::GenericImage;
+use std::fmt;
+use std::path::PathBuf;
+use serde::{Deserialize, Deserializer};
+
+/// Heights and normals at each point of a heightfield's grid, stored row by row.
+#[derive(Default)]
+pub struct HeightfieldData {
+    pub columns: usize,
+    pub rows: usize,
+    /// Heights from zero to one.
+    pub heights: Vec<f64>,
+    pub normals: Vec<Vector3>,
+}
+
+/// Terrain built from a grayscale image, with one grid point per pixel. The image's columns run
+/// along X and its rows along Z.
+#[derive(Deserialize, Serialize)]
+pub struct Heightfield {
+    pub path: PathBuf,
+    /// Corner of the terrain with the lowest X and Z, at the height of black pixels.
+    pub origin: Point,
+    /// Size of the terrain along the X axis.
+    pub width: f64,
+    /// Size of the terrain along the Z axis.
+    pub depth: f64,
+    /// Height of white pixels above the origin.
+    pub height_scale: f64,
+    pub material: Material,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub data: HeightfieldData,
+}
+impl fmt::Debug for Heightfield {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Heightfield({:?})", self.path)
+    }
+}
+impl Heightfield {
+    pub fn load(path: PathBuf,
+                origin: Point,
+                width: f64,
+                depth: f64,
+                height_scale: f64,
+                material: Material)
+                -> Result<Heightfield, String> {
+        let image = image::open(&path)
+            .map_err(|e| format!("Unable to open heightfield image {:?}: {}", path, e))?;
+        let (columns, rows) = image.dimensions();
+        if columns < 2 || rows < 2 {
+            return Err(format!("Heightfield image {:?} must be at least 2x2 pixels", path));
+        }
+        let luma = image.to_luma();
+        let heights = luma.pixels().map(|p| p.data[0] as f64 / 255.0).collect();
+
+        let mut heightfield = Heightfield {
+            path: path,
+            origin: origin,
+            width: width,
+            depth: depth,
+            height_scale: height_scale,
+            material: material,
+            data: HeightfieldData {
+                columns: columns as usize,
+                rows: rows as usize,
+                heights: heights,
+                normals: vec![],
+            },
+        };
+        heightfield.data.normals = heightfield.compute_normals();
+        Ok(heightfield)
+    }
+
+    pub fn cell_width(&self) -> f64 {
+        self.width / (self.data.columns - 1) as f64
+    }
+
+    pub fn cell_depth(&self) -> f64 {
+        self.depth / (self.data.rows - 1) as f64
+    }
+
+    /// The position of the grid point in the given column and row.
+    pub fn vertex(&self, column: usize, row: usize) -> Point {
+        Point {
+            x: self.origin.x + column as f64 * self.cell_width(),
+            y: self.origin.y +
+               self.data.heights[row * self.data.columns + column] * self.height_scale,
+            z: self.origin.z + row as f64 * self.cell_depth(),
+        }
+    }
+
+    pub fn normal(&self, column: usize, row: usize) -> Vector3 {
+        self.data.normals[row * self.data.columns + column]
+    }
+
+    pub fn center(&self) -> Point {
+        self.origin +
+        Vector3 {
+            x: self.width / 2.0,
+            y: 0.0,
+            z: self.depth / 2.0,
+        }
+    }
+
+    fn compute_normals(&self) -> Vec<Vector3> {
+        let (columns, rows) = (self.data.columns, self.data.rows);
+        let mut normals = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                //Central differences, falling back to one-sided ones at the edges.
+                let left = self.vertex(column.saturating_sub(1), row);
+                let right = self.vertex((column + 1).min(columns - 1), row);
+                let near = self.vertex(column, row.saturating_sub(1));
+                let far = self.vertex(column, (row + 1).min(rows - 1));
+                let slope_x = (right.y - left.y) / (right.x - left.x);
+                let slope_z = (far.y - near.y) / (far.z - near.z);
+                normals.push(Vector3 {
+                        x: -slope_x,
+                        y: 1.0,
+                        z: -slope_z,
+                    }
+                    .normalize());
+            }
+        }
+        normals
+    }
+}
+
+pub fn load_heightfield<D>(deserializer: D) -> Result<Heightfield, D::Error>
+    where D: Deserializer
+{
+    let heightfield = Heightfield::deserialize(deserializer)?;
+    Heightfield::load(heightfield.path,
+                      heightfield.origin,
+                      heightfield.width,
+                      heightfield.depth,
+                      heightfield.height_scale,
+                      heightfield.material)
+        .map_err(::serde::de::Error::custom)
+}
@@ -2,17 +2,32 @@
 extern crate serde_derive;
 extern crate image;
 extern crate serde;
+extern crate serde_json;
 
 pub mod scene;
+pub mod camera;
+pub mod animation;
+pub mod mesh;
+pub mod gltf;
+pub mod sdf;
+pub mod heightfield;
+pub mod bvh;
 pub mod vector;
 pub mod point;
 mod rendering;
 mod matrix;
+mod sampling;
 
-use scene::Scene;
+use scene::{Color, Scene};
 use image::{DynamicImage, GenericImage, ImageBuffer, Rgba};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
-use rendering::{Ray, cast_ray};
+use rendering::{render_pixel, render_pixel_adaptive, render_sample, View};
+
+/// Width and height of the tiles an image is split into, so threads can share out the work.
+const TILE_SIZE: u32 = 32;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -23,24 +38,232 @@ pub struct ViewBlock {
     pub height: u32,
 }
 
-pub fn render(block: &ViewBlock, scene: &Scene) -> DynamicImage {
+/// Renders the block using the given number of threads, or one per core if that is zero.
+pub fn render(block: &ViewBlock, scene: &Scene, threads: usize) -> DynamicImage {
     let mut image = DynamicImage::new_rgb8(block.width, block.height);
-    for y in 0..block.height {
-        for x in 0..block.width {
-            let ray = Ray::create_prime(x + block.x, y + block.y, scene);
-            image.put_pixel(x, y, cast_ray(scene, &ray, 0).to_rgba());
-        }
-    }
+    render_block(block, scene, threads, |x, y, color| image.put_pixel(x, y, color.to_rgba()));
     image
 }
 
+/// Renders the block into an existing image using the given number of threads, or one per core
+/// if that is zero.
 pub fn render_into(block: &ViewBlock,
                    scene: &Scene,
-                   image: &mut ImageBuffer<Rgba<u8>, &mut [u8]>) {
-    for y in 0..block.height {
-        for x in 0..block.width {
-            let ray = Ray::create_prime(x + block.x, y + block.y, scene);
-            image.put_pixel(x, y, cast_ray(scene, &ray, 0).to_rgba());
+                   image: &mut ImageBuffer<Rgba<u8>, &mut [u8]>,
+                   threads: usize) {
+    render_block(block, scene, threads, |x, y, color| image.put_pixel(x, y, color.to_rgba()));
+}
+
+fn render_block<F>(block: &ViewBlock, scene: &Scene, threads: usize, put_pixel: F)
+    where F: FnMut(u32, u32, Color)
+{
+    let view = &View::new(scene);
+    let settings = match scene.adaptive_sampling {
+        Some(ref settings) => settings,
+        None => {
+            let render = |x, y| render_pixel(x + block.x, y + block.y, view);
+            render_tiles(block, threads, render, put_pixel);
+            return;
+        }
+    };
+
+    //Take a first look at every pixel through its center, then go back over the pixels that
+    //stand out from a neighbor. The first look takes in a border of pixels around the block
+    //wherever the image carries on past it, so that pixels on the edge of the block are
+    //compared with the same neighbors as if the whole image were rendered at once.
+    let right = (block.x + block.width + 1).min(scene.width).max(block.x + block.width);
+    let bottom = (block.y + block.height + 1).min(scene.height).max(block.y + block.height);
+    let border = ViewBlock {
+        x: block.x.saturating_sub(1),
+        y: block.y.saturating_sub(1),
+        width: right - block.x.saturating_sub(1),
+        height: bottom - block.y.saturating_sub(1),
+    };
+    let width = border.width as usize;
+    let black = Color {
+        red: 0.0,
+        green: 0.0,
+        blue: 0.0,
+    };
+    let mut first_pass = vec![black; width * border.height as usize];
+    let render = |x, y| render_sample(x + border.x, y + border.y, (0.5, 0.5), 0, 1, view);
+    render_tiles(&border, threads, render, |x, y, color| {
+        first_pass[y as usize * width + x as usize] = color
+    });
+
+    //Positions in the first pass are relative to the border's corner.
+    let first_pass = &first_pass;
+    let (left, top) = (block.x - border.x, block.y - border.y);
+    let render = |x: u32, y: u32| {
+        let (x, y) = (x + left, y + top);
+        let color = first_pass[y as usize * width + x as usize];
+        let mut neighbors = vec![];
+        if x > 0 {
+            neighbors.push((x - 1, y));
+        }
+        if x + 1 < border.width {
+            neighbors.push((x + 1, y));
+        }
+        if y > 0 {
+            neighbors.push((x, y - 1));
+        }
+        if y + 1 < border.height {
+            neighbors.push((x, y + 1));
+        }
+        let stands_out = neighbors.iter().any(|&(nx, ny)| {
+            color.contrast(&first_pass[ny as usize * width + nx as usize]) > settings.threshold
+        });
+        if stands_out {
+            render_pixel_adaptive(x + border.x, y + border.y, view, settings)
+        } else {
+            color
+        }
+    };
+    render_tiles(block, threads, render, put_pixel);
+}
+
+/// Splits the block into tiles and renders them in parallel, with each thread taking the next
+/// unrendered tile whenever it finishes one and calling `render` for each of its pixels.
+/// Finished tiles are handed back to this thread, which passes each pixel to `put_pixel`. Both
+/// take positions within the block. Every pixel is rendered on its own, so the result doesn't
+/// depend on how the tiles were shared out.
+fn render_tiles<R, F>(block: &ViewBlock, threads: usize, render: R, mut put_pixel: F)
+    where R: Fn(u32, u32) -> Color + Sync,
+          F: FnMut(u32, u32, Color)
+{
+    let mut tiles = vec![];
+    for y in (0..block.height).step_by(TILE_SIZE as usize) {
+        for x in (0..block.width).step_by(TILE_SIZE as usize) {
+            tiles.push(ViewBlock {
+                x: x,
+                y: y,
+                width: TILE_SIZE.min(block.width - x),
+                height: TILE_SIZE.min(block.height - y),
+            });
         }
     }
+
+    let threads = if threads > 0 {
+        threads
+    } else {
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    };
+    let next_tile = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(tiles.len()) {
+            let sender = sender.clone();
+            let (tiles, next_tile, render) = (&tiles, &next_tile, &render);
+            scope.spawn(move || {
+                while let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                    let mut pixels = Vec::with_capacity((tile.width * tile.height) as usize);
+                    for y in tile.y..tile.y + tile.height {
+                        for x in tile.x..tile.x + tile.width {
+                            pixels.push(render(x, y));
+                        }
+                    }
+                    sender.send((tile, pixels)).unwrap();
+                }
+            });
+        }
+        //Only the workers' senders are left, so this ends once they have all finished.
+        drop(sender);
+
+        for (tile, pixels) in receiver {
+            for (i, color) in pixels.into_iter().enumerate() {
+                let i = i as u32;
+                put_pixel(tile.x + i % tile.width, tile.y + i / tile.width, color);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scene::AdaptiveSampling;
+
+    /// Two spheres over a floor, seen through a wide lens so each pixel's samples differ.
+    const SCENE: &'static str = r#"{
+        "width": 72,
+        "height": 40,
+        "camera": {
+            "position": {"x": 0.0, "y": 0.0, "z": 0.0},
+            "look_at": {"x": 0.0, "y": 0.0, "z": -1.0},
+            "up": {"x": 0.0, "y": 1.0, "z": 0.0},
+            "fov": 90.0,
+            "aperture": 0.2,
+            "focal_distance": 5.0
+        },
+        "elements": [
+            {"Sphere": {
+                "center": {"x": 0.0, "y": 0.0, "z": -5.0},
+                "radius": 1.0,
+                "material": {
+                    "coloration": {"Color": {"red": 0.2, "green": 1.0, "blue": 0.2}},
+                    "albedo": 0.18,
+                    "surface": {"Reflective": {"reflectivity": 0.7}}
+                }
+            }},
+            {"Sphere": {
+                "center": {"x": 2.0, "y": 0.5, "z": -4.0},
+                "radius": 1.0,
+                "material": {
+                    "coloration": {"Color": {"red": 1.0, "green": 1.0, "blue": 1.0}},
+                    "albedo": 0.18,
+                    "surface": {"Refractive": {"index": 1.5, "transparency": 1.0}}
+                }
+            }},
+            {"Plane": {
+                "origin": {"x": 0.0, "y": -2.0, "z": -5.0},
+                "normal": {"x": 0.0, "y": -1.0, "z": 0.0},
+                "material": {
+                    "coloration": {"Color": {"red": 0.2, "green": 0.3, "blue": 1.0}},
+                    "albedo": 0.38,
+                    "surface": "Diffuse"
+                }
+            }}
+        ],
+        "lights": [
+            {"Spherical": {
+                "position": {"x": -2.0, "y": 10.0, "z": -3.0},
+                "color": {"red": 1.0, "green": 1.0, "blue": 1.0},
+                "intensity": 10000.0
+            }}
+        ],
+        "shadow_bias": 1e-13,
+        "max_recursion_depth": 4,
+        "samples_per_pixel": 4
+    }"#;
+
+    fn assert_same_with_threads(scene: &Scene) {
+        //Tiles are 32 pixels across, so the image is split into six of different sizes.
+        let block = ViewBlock {
+            x: 0,
+            y: 0,
+            width: scene.width,
+            height: scene.height,
+        };
+        let single = render(&block, scene, 1);
+        let multiple = render(&block, scene, 4);
+        assert!(single.raw_pixels() == multiple.raw_pixels(),
+                "rendering with 1 and 4 threads gave different images");
+    }
+
+    #[test]
+    fn threads_render_the_same_image() {
+        let scene: Scene = serde_json::from_str(SCENE).unwrap();
+        assert_same_with_threads(&scene);
+    }
+
+    #[test]
+    fn threads_render_the_same_image_with_adaptive_sampling() {
+        let mut scene: Scene = serde_json::from_str(SCENE).unwrap();
+        scene.adaptive_sampling = Some(AdaptiveSampling {
+            threshold: 0.05,
+            max_depth: 2,
+        });
+        assert_same_with_threads(&scene);
+    }
 }
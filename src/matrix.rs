@@ -2,11 +2,15 @@ use std::ops::{Mul, Index, IndexMut};
 use point::Point;
 use vector::Vector3;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct Matrix44 {
     elements: [[f64; 4]; 4],
 }
 impl Matrix44 {
+    pub fn new(elements: [[f64; 4]; 4]) -> Matrix44 {
+        Matrix44 { elements: elements }
+    }
+
     #[cfg_attr(rustfmt, rustfmt_skip)]
     pub fn identity() -> Matrix44 {
         Matrix44 {
@@ -70,13 +74,70 @@ impl Matrix44 {
     #[cfg_attr(rustfmt, rustfmt_skip)]
     pub fn translate(tx: f64, ty:f64, tz: f64) -> Matrix44 {
         Matrix44 {
-            elements: [[0.0, 0.0, 0.0,  tx],
-                       [0.0, 0.0, 0.0,  ty],
-                       [0.0, 0.0, 0.0,  tz],
-                       [0.0, 0.0, 0.0, 0.0]],
+            elements: [[1.0, 0.0, 0.0, 0.0],
+                       [0.0, 1.0, 0.0, 0.0],
+                       [0.0, 0.0, 1.0, 0.0],
+                       [ tx,  ty,  tz, 1.0]],
         }
     }
 
+    /// Builds the rotation described by the unit quaternion (x, y, z, w).
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    pub fn rotate_quaternion(x: f64, y: f64, z: f64, w: f64) -> Matrix44 {
+        Matrix44 {
+            elements: [[1.0 - 2.0 * (y * y + z * z),       2.0 * (x * y + z * w),       2.0 * (x * z - y * w), 0.0],
+                       [      2.0 * (x * y - z * w), 1.0 - 2.0 * (x * x + z * z),       2.0 * (y * z + x * w), 0.0],
+                       [      2.0 * (x * z + y * w),       2.0 * (y * z - x * w), 1.0 - 2.0 * (x * x + y * y), 0.0],
+                       [                        0.0,                         0.0,                         0.0, 1.0]],
+        }
+    }
+
+    /// Builds a camera-to-world transform for a camera at `from` looking towards `to`. The
+    /// camera looks down its own -Z axis, with +Y as up and +X to the right.
+    ///
+    /// If `up` is parallel to the view direction, the world axis furthest from it is used
+    /// instead, and if `from` and `to` are the same point the camera looks down -Z.
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    pub fn look_at(from: Point, to: Point, up: Vector3) -> Matrix44 {
+        let offset = from - to;
+        let forward = if offset.norm() > 0.0 {
+            offset.normalize()
+        } else {
+            Vector3 { x: 0.0, y: 0.0, z: 1.0 }
+        };
+        let mut right = up.cross(&forward);
+        //Also catches a zero or NaN up vector.
+        if !(right.length() > 1e-9 * up.length()) {
+            let (x, y, z) = (forward.x.abs(), forward.y.abs(), forward.z.abs());
+            let axis = if x <= y && x <= z {
+                Vector3 { x: 1.0, y: 0.0, z: 0.0 }
+            } else if y <= z {
+                Vector3 { x: 0.0, y: 1.0, z: 0.0 }
+            } else {
+                Vector3 { x: 0.0, y: 0.0, z: 1.0 }
+            };
+            right = axis.cross(&forward);
+        }
+        let right = right.normalize();
+        let up = forward.cross(&right);
+        Matrix44 {
+            elements: [[  right.x,   right.y,   right.z, 0.0],
+                       [     up.x,      up.y,      up.z, 0.0],
+                       [forward.x, forward.y, forward.z, 0.0],
+                       [   from.x,    from.y,    from.z, 1.0]],
+        }
+    }
+
+    pub fn transpose(&self) -> Matrix44 {
+        let mut result = Matrix44::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                result[i][j] = self[j][i];
+            }
+        }
+        result
+    }
+
     pub fn inverse(&self) -> Matrix44 {
         let mut s = Matrix44::identity();
         let mut t = self.clone();
@@ -204,3 +265,107 @@ impl Mul<Vector3> for Matrix44 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9,
+                "expected {}, got {}",
+                expected,
+                actual);
+    }
+
+    fn assert_orthonormal(m: &Matrix44) {
+        for i in 0..3 {
+            for j in 0..3 {
+                let dot = m[i][0] * m[j][0] + m[i][1] * m[j][1] + m[i][2] * m[j][2];
+                assert_close(dot, if i == j { 1.0 } else { 0.0 });
+            }
+        }
+    }
+
+    fn assert_point(actual: Point, expected: Point) {
+        assert_close(actual.x, expected.x);
+        assert_close(actual.y, expected.y);
+        assert_close(actual.z, expected.z);
+    }
+
+    #[test]
+    fn translate_moves_points_but_not_vectors() {
+        let m = Matrix44::translate(1.0, -2.0, 3.0);
+        assert_point(m * Point { x: 1.0, y: 1.0, z: 1.0 },
+                     Point { x: 2.0, y: -1.0, z: 4.0 });
+        let v = m * Vector3 { x: 1.0, y: 1.0, z: 1.0 };
+        assert_point(Point::zero() + v, Point { x: 1.0, y: 1.0, z: 1.0 });
+        assert_point(m.inverse() * Point { x: 2.0, y: -1.0, z: 4.0 },
+                     Point { x: 1.0, y: 1.0, z: 1.0 });
+    }
+
+    #[test]
+    fn rotate_quaternion_matches_axis_rotations() {
+        let t = 0.7f64;
+        let (sin, cos) = ((t / 2.0).sin(), (t / 2.0).cos());
+        let pairs = [(Matrix44::rotate_quaternion(sin, 0.0, 0.0, cos), Matrix44::rotate_x(t)),
+                     (Matrix44::rotate_quaternion(0.0, sin, 0.0, cos), Matrix44::rotate_y(t)),
+                     (Matrix44::rotate_quaternion(0.0, 0.0, sin, cos), Matrix44::rotate_z(t))];
+        for &(q, r) in &pairs {
+            assert_orthonormal(&q);
+            for i in 0..4 {
+                for j in 0..4 {
+                    assert_close(q[i][j], r[i][j]);
+                }
+            }
+        }
+
+        //A quarter turn around +Y takes +X to -Z.
+        let half = 0.5f64.sqrt();
+        assert_point(Matrix44::rotate_quaternion(0.0, half, 0.0, half) *
+                     Point { x: 1.0, y: 0.0, z: 0.0 },
+                     Point { x: 0.0, y: 0.0, z: -1.0 });
+    }
+
+    #[test]
+    fn look_at_places_the_camera() {
+        let up = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+        let m = Matrix44::look_at(Point::zero(), Point { x: 0.0, y: 0.0, z: -1.0 }, up);
+        for i in 0..4 {
+            for j in 0..4 {
+                assert_close(m[i][j], Matrix44::identity()[i][j]);
+            }
+        }
+
+        let from = Point { x: 1.0, y: 2.0, z: 3.0 };
+        let to = Point { x: 4.0, y: 2.0, z: -1.0 };
+        let m = Matrix44::look_at(from, to, up);
+        assert_orthonormal(&m);
+        assert_point(m * Point::zero(), from);
+        assert_point(m * Point { x: 0.0, y: 0.0, z: -5.0 }, to);
+        //Level with the target, so camera +X stays horizontal and +Y stays world up.
+        assert_close(m[0][1], 0.0);
+        assert_point(Point::zero() + m * up, Point { x: 0.0, y: 1.0, z: 0.0 });
+    }
+
+    #[test]
+    fn look_at_with_up_parallel_to_view() {
+        let from = Point { x: 1.0, y: 2.0, z: 3.0 };
+        for &(dy, up_y) in &[(-1.0, 1.0), (1.0, 1.0), (-1.0, -5.0)] {
+            let to = from + Vector3 { x: 0.0, y: dy, z: 0.0 };
+            let m = Matrix44::look_at(from, to, Vector3 { x: 0.0, y: up_y, z: 0.0 });
+            assert_orthonormal(&m);
+            //The camera still looks down its -Z axis towards the target.
+            let target = m * Point { x: 0.0, y: 0.0, z: -1.0 };
+            assert_close(target.x, to.x);
+            assert_close(target.y, to.y);
+            assert_close(target.z, to.z);
+        }
+    }
+
+    #[test]
+    fn look_at_with_degenerate_input() {
+        let from = Point { x: 1.0, y: 2.0, z: 3.0 };
+        assert_orthonormal(&Matrix44::look_at(from, from, Vector3 { x: 0.0, y: 1.0, z: 0.0 }));
+        assert_orthonormal(&Matrix44::look_at(from, Point::zero(), Vector3::zero()));
+    }
+}
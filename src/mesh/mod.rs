@@ -0,0 +1,155 @@
+use bvh::{BoundingBox, Bvh};
+use point::Point;
+use vector::Vector3;
+use scene::{Color, Material, TextureCoords};
+use std::fmt;
+use std::path::PathBuf;
+use serde::{Deserialize, Deserializer};
+
+mod obj;
+mod ply;
+mod stl;
+
+/// A triangle of a mesh, given as indices into the mesh's vertex data.
+#[derive(Debug, Clone)]
+pub struct Face {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub texture_coords: Option<[usize; 3]>,
+    /// Index into the mesh's materials; faces without one use the mesh's own material.
+    pub material: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct MeshData {
+    pub positions: Vec<Point>,
+    pub normals: Vec<Vector3>,
+    pub texture_coords: Vec<TextureCoords>,
+    /// Per-position vertex colors; empty if the mesh has none. They are only shown by materials
+    /// with the `VertexColor` coloration.
+    pub colors: Vec<Color>,
+    pub faces: Vec<Face>,
+    pub materials: Vec<Material>,
+}
+impl MeshData {
+    pub fn centroid(&self) -> Point {
+        let count = self.positions.len().max(1) as f64;
+        let sum = self.positions
+            .iter()
+            .fold(Vector3::zero(), |sum, p| sum + (*p - Point::zero()));
+        Point::zero() + sum * (1.0 / count)
+    }
+
+    pub fn face_normal(&self, face: &Face) -> Vector3 {
+        let v0 = self.positions[face.vertices[0]];
+        let edge1 = self.positions[face.vertices[1]] - v0;
+        let edge2 = self.positions[face.vertices[2]] - v0;
+        edge1.cross(&edge2).normalize()
+    }
+
+    /// Gives every face without normals smooth normals, averaged from the faces around each
+    /// vertex and weighted by their area.
+    pub fn compute_smooth_normals(&mut self) {
+        if self.faces.iter().all(|f| f.normals.is_some()) {
+            return;
+        }
+
+        let mut vertex_normals = vec![Vector3::zero(); self.positions.len()];
+        for face in &self.faces {
+            let v0 = self.positions[face.vertices[0]];
+            let edge1 = self.positions[face.vertices[1]] - v0;
+            let edge2 = self.positions[face.vertices[2]] - v0;
+            let weighted_normal = edge1.cross(&edge2);
+            for &vertex in &face.vertices {
+                vertex_normals[vertex] = vertex_normals[vertex] + weighted_normal;
+            }
+        }
+
+        let base = self.normals.len();
+        self.normals.extend(vertex_normals.iter().map(|n| if n.norm() > 0.0 {
+            n.normalize()
+        } else {
+            *n
+        }));
+        for face in self.faces.iter_mut().filter(|f| f.normals.is_none()) {
+            face.normals = Some([base + face.vertices[0],
+                                 base + face.vertices[1],
+                                 base + face.vertices[2]]);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Mesh {
+    pub path: PathBuf,
+    /// Used for faces that aren't assigned a material by the mesh file. Materials read from
+    /// the mesh file take their albedo from this one.
+    pub material: Material,
+    /// Makes the back faces of the mesh visible too, for surfaces that aren't closed.
+    #[serde(default)]
+    pub double_sided: bool,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub data: MeshData,
+    /// Hierarchy over the faces, which keeps tracing large meshes fast.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub bvh: Bvh,
+}
+impl fmt::Debug for Mesh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mesh({:?})", self.path)
+    }
+}
+impl Mesh {
+    pub fn new(path: PathBuf, material: Material, double_sided: bool, data: MeshData) -> Mesh {
+        let mut mesh = Mesh {
+            path: path,
+            material: material,
+            double_sided: double_sided,
+            data: data,
+            bvh: Bvh::default(),
+        };
+        mesh.build_bvh();
+        mesh
+    }
+
+    /// Rebuilds the hierarchy over the faces. This should be done after moving any vertices.
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<_> = self.data
+            .faces
+            .iter()
+            .map(|face| {
+                let positions = &self.data.positions;
+                Some(BoundingBox::from_points(&[positions[face.vertices[0]],
+                                                positions[face.vertices[1]],
+                                                positions[face.vertices[2]]]))
+            })
+            .collect();
+        self.bvh = Bvh::build(&bounds);
+    }
+
+    /// Loads a mesh from an OBJ, PLY, binary STL or glTF file, chosen by the file's extension.
+    pub fn load(path: PathBuf, material: Material) -> Result<Mesh, String> {
+        let extension = path.extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let data = match extension.as_str() {
+            "obj" => obj::load_obj(&path, &material)?,
+            "ply" => ply::load_ply(&path)?,
+            "stl" => stl::load_stl(&path)?,
+            "gltf" | "glb" => ::gltf::load_mesh_data(&path, &material)?,
+            _ => return Err(format!("Unsupported mesh file format: {:?}", path)),
+        };
+        Ok(Mesh::new(path, material, false, data))
+    }
+}
+
+pub fn load_mesh<D>(deserializer: D) -> Result<Mesh, D::Error>
+    where D: Deserializer
+{
+    let mesh = Mesh::deserialize(deserializer)?;
+    let double_sided = mesh.double_sided;
+    let mut mesh = Mesh::load(mesh.path, mesh.material).map_err(::serde::de::Error::custom)?;
+    mesh.double_sided = double_sided;
+    Ok(mesh)
+}
@@ -0,0 +1,331 @@
+use point::Point;
+use vector::Vector3;
+use scene::{Color, Coloration, Material, SurfaceType, Texture, TextureCoords};
+use super::{Face, MeshData};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use image;
+
+fn parse_floats(path: &Path, line_number: usize, tokens: &[&str]) -> Result<Vec<f64>, String> {
+    tokens.iter()
+        .map(|t| {
+            t.parse::<f64>()
+                .map_err(|_| format!("{:?} line {}: invalid number {:?}", path, line_number, t))
+        })
+        .collect()
+}
+
+/// Converts a one-based (or negative, relative to the end) OBJ index into a zero-based one.
+fn obj_index(token: &str, count: usize, path: &Path, line_number: usize) -> Result<usize, String> {
+    let index = token.parse::<i64>()
+        .map_err(|_| format!("{:?} line {}: invalid index {:?}", path, line_number, token))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!("{:?} line {}: index {} out of range", path, line_number, index));
+    }
+    Ok(resolved as usize)
+}
+
+pub fn load_obj(path: &Path, base_material: &Material) -> Result<MeshData, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open mesh file {:?}: {}", path, e))?;
+    read_obj(BufReader::new(file), path, base_material)
+}
+
+/// Parses OBJ data read from `path`, which is used for error messages and to find material
+/// libraries.
+fn read_obj<R: BufRead>(reader: R,
+                        path: &Path,
+                        base_material: &Material)
+                        -> Result<MeshData, String> {
+    let mut data = MeshData::default();
+    let mut material_names = HashMap::new();
+    let mut current_material = None;
+
+    for (line_index, line) in reader.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.map_err(|e| format!("Unable to read mesh file {:?}: {}", path, e))?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        match tokens[0] {
+            "v" if tokens.len() >= 4 => {
+                let v = parse_floats(path, line_number, &tokens[1..4])?;
+                data.positions.push(Point {
+                    x: v[0],
+                    y: v[1],
+                    z: v[2],
+                });
+            }
+            "vn" if tokens.len() >= 4 => {
+                let n = parse_floats(path, line_number, &tokens[1..4])?;
+                data.normals.push(Vector3 {
+                        x: n[0],
+                        y: n[1],
+                        z: n[2],
+                    }
+                    .normalize());
+            }
+            "vt" if tokens.len() >= 3 => {
+                let t = parse_floats(path, line_number, &tokens[1..3])?;
+                //OBJ puts the texture origin at the bottom left; images start at the top left.
+                data.texture_coords.push(TextureCoords {
+                    x: t[0] as f32,
+                    y: 1.0 - t[1] as f32,
+                });
+            }
+            "f" if tokens.len() >= 4 => {
+                let mut corners = vec![];
+                for corner in &tokens[1..] {
+                    let mut parts = corner.split('/');
+                    let vertex = obj_index(parts.next().unwrap(),
+                                           data.positions.len(),
+                                           path,
+                                           line_number)?;
+                    let texture_coords = match parts.next() {
+                        Some(t) if !t.is_empty() => {
+                            Some(obj_index(t, data.texture_coords.len(), path, line_number)?)
+                        }
+                        _ => None,
+                    };
+                    let normal = match parts.next() {
+                        Some(n) if !n.is_empty() => {
+                            Some(obj_index(n, data.normals.len(), path, line_number)?)
+                        }
+                        _ => None,
+                    };
+                    corners.push((vertex, texture_coords, normal));
+                }
+
+                //Triangulate polygons as a fan around the first corner.
+                for i in 1..(corners.len() - 1) {
+                    let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+                    data.faces.push(Face {
+                        vertices: [a.0, b.0, c.0],
+                        texture_coords: match (a.1, b.1, c.1) {
+                            (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                            _ => None,
+                        },
+                        normals: match (a.2, b.2, c.2) {
+                            (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                            _ => None,
+                        },
+                        material: current_material,
+                    });
+                }
+            }
+            "mtllib" if tokens.len() >= 2 => {
+                let mtl_path = path.with_file_name(tokens[1]);
+                for (name, material) in load_mtl(&mtl_path, base_material)? {
+                    material_names.insert(name, data.materials.len());
+                    data.materials.push(material);
+                }
+            }
+            "usemtl" if tokens.len() >= 2 => {
+                current_material = material_names.get(tokens[1]).cloned();
+            }
+            _ => {}
+        }
+    }
+
+    data.compute_smooth_normals();
+    Ok(data)
+}
+
+fn load_mtl(path: &Path, base_material: &Material) -> Result<Vec<(String, Material)>, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Unable to open material file {:?}: {}", path, e))?;
+
+    let mut materials = vec![];
+    let mut current: Option<MtlMaterial> = None;
+    for (line_index, line) in BufReader::new(file).lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.map_err(|e| format!("Unable to read material file {:?}: {}", path, e))?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if tokens[0] == "newmtl" && tokens.len() >= 2 {
+            if let Some(material) = current.take() {
+                materials.push(material.to_material(base_material)?);
+            }
+            current = Some(MtlMaterial::new(tokens[1]));
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+        match tokens[0] {
+            "Kd" if tokens.len() >= 4 => {
+                let c = parse_floats(path, line_number, &tokens[1..4])?;
+                material.diffuse = Color {
+                    red: c[0] as f32,
+                    green: c[1] as f32,
+                    blue: c[2] as f32,
+                };
+            }
+            "Ks" if tokens.len() >= 4 => {
+                let c = parse_floats(path, line_number, &tokens[1..4])?;
+                material.specular = ((c[0] + c[1] + c[2]) / 3.0) as f32;
+            }
+            "map_Kd" if tokens.len() >= 2 => {
+                material.texture = Some(path.with_file_name(tokens[tokens.len() - 1]));
+            }
+            "Ni" if tokens.len() >= 2 => {
+                material.index = parse_floats(path, line_number, &tokens[1..2])?[0] as f32;
+            }
+            "d" if tokens.len() >= 2 => {
+                material.opacity = parse_floats(path, line_number, &tokens[1..2])?[0] as f32;
+            }
+            "Tr" if tokens.len() >= 2 => {
+                material.opacity = 1.0 - parse_floats(path, line_number, &tokens[1..2])?[0] as f32;
+            }
+            "illum" if tokens.len() >= 2 => {
+                material.illum = tokens[1].parse().unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+    if let Some(material) = current.take() {
+        materials.push(material.to_material(base_material)?);
+    }
+    Ok(materials)
+}
+
+struct MtlMaterial {
+    name: String,
+    diffuse: Color,
+    texture: Option<PathBuf>,
+    specular: f32,
+    index: f32,
+    opacity: f32,
+    illum: u32,
+}
+impl MtlMaterial {
+    fn new(name: &str) -> MtlMaterial {
+        MtlMaterial {
+            name: name.to_string(),
+            diffuse: Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            texture: None,
+            specular: 0.0,
+            index: 1.0,
+            opacity: 1.0,
+            illum: 0,
+        }
+    }
+
+    fn to_material(self, base_material: &Material) -> Result<(String, Material), String> {
+        let coloration = match self.texture {
+            Some(path) => {
+                let texture = image::open(&path)
+                    .map_err(|_| format!("Unable to open texture file: {:?}", path))?;
+                Coloration::Texture(Texture {
+                    path: path,
+                    texture: texture,
+                })
+            }
+            None => Coloration::Color(self.diffuse),
+        };
+        let surface = if self.opacity < 1.0 {
+            SurfaceType::Refractive {
+                index: self.index,
+                transparency: 1.0 - self.opacity,
+            }
+        } else if self.illum == 3 && self.specular > 0.0 {
+            SurfaceType::Reflective { reflectivity: self.specular }
+        } else {
+            base_material.surface.clone()
+        };
+        let material = Material {
+            coloration: coloration,
+            albedo: base_material.albedo,
+            surface: surface,
+        };
+        Ok((self.name, material))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OBJ: &'static str = concat!("# A unit quad with a triangle beside it\n",
+                                      "v 0 0 0\n",
+                                      "v 1 0 0\n",
+                                      "v 1 1 0\n",
+                                      "v 0 1 0\n",
+                                      "vt 0 0\n",
+                                      "vt 1 0\n",
+                                      "vt 1 1\n",
+                                      "vt 0 1\n",
+                                      "vn 0 0 2\n",
+                                      "f 1/1/1 2/2/1 3/3/1 4/4/1\n",
+                                      "\n",
+                                      "v 2 0 0\n",
+                                      "f -4/-4/-1 -3/-3/-1 -1/-1/-1\n",
+                                      "f 2//1 5//1 3//1\n");
+
+    fn read(obj: &str) -> Result<MeshData, String> {
+        let material = Material {
+            coloration: Coloration::Color(Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            }),
+            albedo: 0.18,
+            surface: SurfaceType::Diffuse,
+        };
+        read_obj(obj.as_bytes(), Path::new("inline.obj"), &material)
+    }
+
+    #[test]
+    fn reads_vertex_data() {
+        let data = read(OBJ).unwrap();
+        assert_eq!(data.positions.len(), 5);
+        assert_eq!((data.positions[4].x, data.positions[4].y), (2.0, 0.0));
+        assert_eq!(data.normals.len(), 1);
+        assert_eq!((data.normals[0].x, data.normals[0].y, data.normals[0].z),
+                   (0.0, 0.0, 1.0));
+        //Texture coordinates are flipped vertically to match images.
+        let coords: Vec<_> = data.texture_coords.iter().map(|t| (t.x, t.y)).collect();
+        assert_eq!(coords, vec![(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)]);
+    }
+
+    #[test]
+    fn reads_faces() {
+        let data = read(OBJ).unwrap();
+        let faces: Vec<_> = data.faces
+            .iter()
+            .map(|f| (f.vertices, f.texture_coords, f.normals))
+            .collect();
+        assert_eq!(faces,
+                   vec![//The quad, split into a fan around its first corner.
+                        ([0, 1, 2], Some([0, 1, 2]), Some([0, 0, 0])),
+                        ([0, 2, 3], Some([0, 2, 3]), Some([0, 0, 0])),
+                        //Negative indices count back from the last vertex read so far.
+                        ([1, 2, 4], Some([0, 1, 3]), Some([0, 0, 0])),
+                        ([1, 4, 2], None, Some([0, 0, 0]))]);
+    }
+
+    #[test]
+    fn rejects_indices_out_of_range() {
+        assert!(read("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 4\n").is_err());
+        assert!(read("v 0 0 0\nv 1 0 0\nv 1 1 0\nf -4 2 3\n").is_err());
+        assert!(read("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 0 1 2\n").is_err());
+        assert!(read("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1/1 2/1 3/1\n").is_err());
+    }
+}
@@ -0,0 +1,471 @@
+use point::Point;
+use vector::Vector3;
+use scene::{Color, TextureCoords};
+use super::{Face, MeshData};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::SplitWhitespace;
+use image::{Pixel, Rgba};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+impl ScalarType {
+    fn parse(name: &str) -> Option<ScalarType> {
+        match name {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    /// Scale that maps this type's values onto [0, 1] when used as a color channel.
+    fn color_scale(&self) -> f64 {
+        match *self {
+            ScalarType::UInt8 => 1.0 / 255.0,
+            ScalarType::UInt16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(ScalarType, String),
+    List(ScalarType, ScalarType, String),
+}
+impl Property {
+    fn name(&self) -> &str {
+        match *self {
+            Property::Scalar(_, ref name) |
+            Property::List(_, _, ref name) => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+impl Element {
+    fn scalar_index(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| match *p {
+            Property::Scalar(_, ref name) => names.contains(&name.as_str()),
+            _ => false,
+        })
+    }
+}
+
+enum Body<'a> {
+    Ascii(SplitWhitespace<'a>),
+    Binary {
+        data: &'a [u8],
+        position: usize,
+        big_endian: bool,
+    },
+}
+impl<'a> Body<'a> {
+    fn read(&mut self, scalar: ScalarType) -> Result<f64, String> {
+        match *self {
+            Body::Ascii(ref mut tokens) => {
+                let token = tokens.next().ok_or("unexpected end of file")?;
+                token.parse::<f64>().map_err(|_| format!("invalid number {:?}", token))
+            }
+            Body::Binary { data, ref mut position, big_endian } => {
+                let size = scalar.size();
+                if *position + size > data.len() {
+                    return Err("unexpected end of file".to_string());
+                }
+                let mut bytes = [0u8; 8];
+                bytes[..size].copy_from_slice(&data[*position..*position + size]);
+                *position += size;
+                if big_endian {
+                    bytes[..size].reverse();
+                }
+
+                let value = match scalar {
+                    ScalarType::Int8 => bytes[0] as i8 as f64,
+                    ScalarType::UInt8 => bytes[0] as f64,
+                    ScalarType::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::UInt16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::Int32 => {
+                        i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ScalarType::UInt32 => {
+                        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ScalarType::Float32 => {
+                        f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ScalarType::Float64 => f64::from_le_bytes(bytes),
+                };
+                Ok(value)
+            }
+        }
+    }
+
+    /// Reads all properties of one element instance. Lists are returned in full; scalars as
+    /// one-item lists.
+    fn read_element(&mut self, element: &Element) -> Result<Vec<Vec<f64>>, String> {
+        let mut values = Vec::with_capacity(element.properties.len());
+        for property in &element.properties {
+            match *property {
+                Property::Scalar(scalar, _) => values.push(vec![self.read(scalar)?]),
+                Property::List(count_type, item_type, _) => {
+                    let count = self.read(count_type)?;
+                    if count < 0.0 {
+                        return Err(format!("negative list length {}", count));
+                    }
+                    let mut items = vec![];
+                    for _ in 0..(count as usize) {
+                        items.push(self.read(item_type)?);
+                    }
+                    values.push(items);
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+fn parse_header(header: &str) -> Result<(Format, Vec<Element>), String> {
+    let mut lines = header.lines();
+    if lines.next().map(|l| l.trim()) != Some("ply") {
+        return Err("missing 'ply' magic number".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first().cloned() {
+            Some("format") if tokens.len() >= 2 => {
+                format = Some(match tokens[1] {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    other => return Err(format!("unknown format {:?}", other)),
+                });
+            }
+            Some("element") if tokens.len() >= 3 => {
+                let count = tokens[2]
+                    .parse()
+                    .map_err(|_| format!("invalid element count {:?}", tokens[2]))?;
+                elements.push(Element {
+                    name: tokens[1].to_string(),
+                    count: count,
+                    properties: vec![],
+                });
+            }
+            Some("property") => {
+                let element = elements.last_mut()
+                    .ok_or("property declared before any element")?;
+                let property = if tokens.len() >= 5 && tokens[1] == "list" {
+                    let count_type = ScalarType::parse(tokens[2])
+                        .ok_or_else(|| format!("unknown property type {:?}", tokens[2]))?;
+                    let item_type = ScalarType::parse(tokens[3])
+                        .ok_or_else(|| format!("unknown property type {:?}", tokens[3]))?;
+                    Property::List(count_type, item_type, tokens[4].to_string())
+                } else if tokens.len() >= 3 {
+                    let scalar = ScalarType::parse(tokens[1])
+                        .ok_or_else(|| format!("unknown property type {:?}", tokens[1]))?;
+                    Property::Scalar(scalar, tokens[2].to_string())
+                } else {
+                    return Err(format!("malformed property line {:?}", line));
+                };
+                element.properties.push(property);
+            }
+            Some("comment") | Some("obj_info") | Some("end_header") | None => {}
+            _ => return Err(format!("unexpected header line {:?}", line)),
+        }
+    }
+
+    format.map(|f| (f, elements)).ok_or_else(|| "missing format line".to_string())
+}
+
+pub fn load_ply(path: &Path) -> Result<MeshData, String> {
+    let mut bytes = vec![];
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|e| format!("Unable to open mesh file {:?}: {}", path, e))?;
+    read_ply(&bytes).map_err(|e| format!("Invalid PLY file {:?}: {}", path, e))
+}
+
+fn read_ply(bytes: &[u8]) -> Result<MeshData, String> {
+    const END_HEADER: &'static [u8] = b"end_header";
+    let header_end = bytes.windows(END_HEADER.len())
+        .position(|w| w == END_HEADER)
+        .ok_or("missing end_header")?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map(|p| header_end + p + 1)
+        .unwrap_or(bytes.len());
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+    let (format, elements) = parse_header(&header)?;
+
+    let ascii_body;
+    let mut body = match format {
+        Format::Ascii => {
+            ascii_body = String::from_utf8_lossy(&bytes[body_start..]);
+            Body::Ascii(ascii_body.split_whitespace())
+        }
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => {
+            Body::Binary {
+                data: &bytes[body_start..],
+                position: 0,
+                big_endian: format == Format::BinaryBigEndian,
+            }
+        }
+    };
+
+    let mut data = MeshData::default();
+    let mut face_normals = false;
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => face_normals = read_vertices(&mut body, element, &mut data)?,
+            "face" => read_faces(&mut body, element, &mut data)?,
+            _ => {
+                for _ in 0..element.count {
+                    body.read_element(element)?;
+                }
+            }
+        }
+    }
+
+    let vertex_count = data.positions.len();
+    let has_texture_coords = !data.texture_coords.is_empty();
+    for face in &mut data.faces {
+        if face.vertices.iter().any(|&v| v >= vertex_count) {
+            return Err(format!("face refers to missing vertex {:?}", face.vertices));
+        }
+        if face_normals {
+            face.normals = Some(face.vertices);
+        }
+        if has_texture_coords {
+            face.texture_coords = Some(face.vertices);
+        }
+    }
+    data.compute_smooth_normals();
+    Ok(data)
+}
+
+/// Reads the vertex element, returning whether the vertices carried normals.
+fn read_vertices(body: &mut Body, element: &Element, data: &mut MeshData) -> Result<bool, String> {
+    let position = match (element.scalar_index(&["x"]),
+                          element.scalar_index(&["y"]),
+                          element.scalar_index(&["z"])) {
+        (Some(x), Some(y), Some(z)) => [x, y, z],
+        _ => return Err("vertex element lacks x, y and z properties".to_string()),
+    };
+    let normal = match (element.scalar_index(&["nx"]),
+                        element.scalar_index(&["ny"]),
+                        element.scalar_index(&["nz"])) {
+        (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+        _ => None,
+    };
+    let texture_coords = match (element.scalar_index(&["u", "s", "texture_u"]),
+                                element.scalar_index(&["v", "t", "texture_v"])) {
+        (Some(u), Some(v)) => Some([u, v]),
+        _ => None,
+    };
+    let color = match (element.scalar_index(&["red", "r"]),
+                       element.scalar_index(&["green", "g"]),
+                       element.scalar_index(&["blue", "b"])) {
+        (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+        _ => None,
+    };
+    let color_scale = color.map(|c| match element.properties[c[0]] {
+            Property::Scalar(scalar, _) => scalar.color_scale(),
+            _ => 1.0,
+        })
+        .unwrap_or(1.0);
+
+    for _ in 0..element.count {
+        let values = body.read_element(element)?;
+        let value = |i: usize| values[i][0];
+
+        data.positions.push(Point {
+            x: value(position[0]),
+            y: value(position[1]),
+            z: value(position[2]),
+        });
+        if let Some(n) = normal {
+            data.normals.push(Vector3 {
+                    x: value(n[0]),
+                    y: value(n[1]),
+                    z: value(n[2]),
+                }
+                .normalize());
+        }
+        if let Some(t) = texture_coords {
+            //PLY puts the texture origin at the bottom left; images start at the top left.
+            data.texture_coords.push(TextureCoords {
+                x: value(t[0]) as f32,
+                y: 1.0 - value(t[1]) as f32,
+            });
+        }
+        if let Some(c) = color {
+            let channel = |i: usize| (value(c[i]) * color_scale * 255.0).max(0.0).min(255.0) as u8;
+            let rgba = Rgba::from_channels(channel(0), channel(1), channel(2), 255);
+            data.colors.push(Color::from_rgba(rgba));
+        }
+    }
+    Ok(normal.is_some())
+}
+
+fn read_faces(body: &mut Body, element: &Element, data: &mut MeshData) -> Result<(), String> {
+    let indices = element.properties
+        .iter()
+        .position(|p| match *p {
+            Property::List(..) => p.name() == "vertex_indices" || p.name() == "vertex_index",
+            _ => false,
+        })
+        .ok_or("face element lacks a vertex_indices list")?;
+
+    for _ in 0..element.count {
+        let values = body.read_element(element)?;
+        let corners = &values[indices];
+        if corners.len() < 3 {
+            return Err(format!("face with only {} vertices", corners.len()));
+        }
+        if corners.iter().any(|&c| c < 0.0) {
+            return Err(format!("negative vertex index in face {:?}", corners));
+        }
+
+        //Triangulate polygons as a fan around the first corner.
+        for i in 1..(corners.len() - 1) {
+            data.faces.push(Face {
+                vertices: [corners[0] as usize, corners[i] as usize, corners[i + 1] as usize],
+                normals: None,
+                texture_coords: None,
+                material: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [[f32; 3]; 4] = [[0.0, 0.0, 0.0],
+                                      [1.0, 0.0, 0.0],
+                                      [1.0, 1.0, 0.0],
+                                      [0.0, 1.0, 0.0]];
+    const COLORS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    fn header(format: &str) -> String {
+        format!(concat!("ply\n",
+                        "format {} 1.0\n",
+                        "comment a unit quad\n",
+                        "element vertex 4\n",
+                        "property float x\n",
+                        "property float y\n",
+                        "property float z\n",
+                        "property uchar red\n",
+                        "property uchar green\n",
+                        "property uchar blue\n",
+                        "element face 1\n",
+                        "property list uchar int vertex_indices\n",
+                        "end_header\n"),
+                format)
+    }
+
+    /// Encodes the quad's body with the given byte order for multi-byte values.
+    fn binary(format: &str, to_bytes: fn([u8; 4]) -> [u8; 4]) -> Vec<u8> {
+        let mut bytes = header(format).into_bytes();
+        for (position, color) in POSITIONS.iter().zip(COLORS.iter()) {
+            for &coordinate in position {
+                bytes.extend_from_slice(&to_bytes(coordinate.to_le_bytes()));
+            }
+            bytes.extend_from_slice(color);
+        }
+        bytes.push(4);
+        for index in 0..4i32 {
+            bytes.extend_from_slice(&to_bytes(index.to_le_bytes()));
+        }
+        bytes
+    }
+
+    fn assert_quad(data: &MeshData) {
+        assert_eq!(data.positions.len(), 4);
+        for (actual, expected) in data.positions.iter().zip(POSITIONS.iter()) {
+            assert_eq!([actual.x as f32, actual.y as f32, actual.z as f32], *expected);
+        }
+        let colors: Vec<[f32; 3]> = data.colors.iter().map(|c| [c.red, c.green, c.blue]).collect();
+        assert_eq!(colors,
+                   vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0, 1.0]]);
+        let faces: Vec<[usize; 3]> = data.faces.iter().map(|f| f.vertices).collect();
+        assert_eq!(faces, vec![[0, 1, 2], [0, 2, 3]]);
+        assert!(data.faces.iter().all(|f| f.normals.is_some()));
+    }
+
+    #[test]
+    fn reads_ascii() {
+        let body = "0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n";
+        let file = header("ascii") + body;
+        assert_quad(&read_ply(file.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_little_endian() {
+        assert_quad(&read_ply(&binary("binary_little_endian", |b| b)).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_big_endian() {
+        let reverse = |mut b: [u8; 4]| {
+            b.reverse();
+            b
+        };
+        assert_quad(&read_ply(&binary("binary_big_endian", reverse)).unwrap());
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        let bytes = binary("binary_little_endian", |b| b);
+        assert!(read_ply(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_faces_with_missing_vertices() {
+        let body = "0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n3 0 1 4\n";
+        let file = header("ascii") + body;
+        assert!(read_ply(file.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use point::Point;
+use super::{Face, MeshData};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const HEADER_SIZE: usize = 84;
+const TRIANGLE_SIZE: usize = 50;
+
+pub fn load_stl(path: &Path) -> Result<MeshData, String> {
+    let mut bytes = vec![];
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|e| format!("Unable to open mesh file {:?}: {}", path, e))?;
+    read_stl(&bytes).map_err(|e| format!("Invalid STL file {:?}: {}", path, e))
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f64 {
+    f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]) as
+    f64
+}
+
+fn read_stl(bytes: &[u8]) -> Result<MeshData, String> {
+    //Binary files may also start with "solid", so only blame ASCII once the size is wrong.
+    let ascii = bytes.starts_with(b"solid");
+    if bytes.len() < HEADER_SIZE {
+        if ascii {
+            return Err("ASCII STL files are not supported".to_string());
+        }
+        return Err("file is too short to be a binary STL".to_string());
+    }
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    let expected = HEADER_SIZE + count * TRIANGLE_SIZE;
+    if bytes.len() != expected {
+        if ascii {
+            return Err("ASCII STL files are not supported".to_string());
+        }
+        return Err(format!("expected {} bytes for {} triangles, found {}",
+                           expected,
+                           count,
+                           bytes.len()));
+    }
+
+    //STL stores each triangle's corners separately and its normals are often unreliable, so
+    //faces are shaded flat from their own geometry.
+    let mut data = MeshData::default();
+    for triangle in 0..count {
+        let start = HEADER_SIZE + triangle * TRIANGLE_SIZE;
+        let base = data.positions.len();
+        for corner in 0..3 {
+            let offset = start + 12 + corner * 12;
+            data.positions.push(Point {
+                x: read_f32(bytes, offset),
+                y: read_f32(bytes, offset + 4),
+                z: read_f32(bytes, offset + 8),
+            });
+        }
+
+        let face = Face {
+            vertices: [base, base + 1, base + 2],
+            normals: Some([data.normals.len(); 3]),
+            texture_coords: None,
+            material: None,
+        };
+        data.normals.push(data.face_normal(&face));
+        data.faces.push(face);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a binary STL whose header claims `count` triangles but holds `triangles`.
+    fn stl(count: u32, triangles: &[[[f32; 3]; 3]]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 80];
+        bytes.extend_from_slice(&count.to_le_bytes());
+        for triangle in triangles {
+            //The stored normal is ignored, so leave it zero.
+            bytes.extend_from_slice(&[0u8; 12]);
+            for corner in triangle {
+                for &coordinate in corner {
+                    bytes.extend_from_slice(&coordinate.to_le_bytes());
+                }
+            }
+            bytes.extend_from_slice(&[0u8; 2]);
+        }
+        bytes
+    }
+
+    const TRIANGLES: [[[f32; 3]; 3]; 2] = [[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+                                           [[0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [1.0, 0.0, 1.0]]];
+
+    #[test]
+    fn reads_triangles_with_flat_normals() {
+        let data = read_stl(&stl(2, &TRIANGLES)).unwrap();
+        assert_eq!(data.positions.len(), 6);
+        assert_eq!(data.faces.len(), 2);
+        assert_eq!(data.faces[1].vertices, [3, 4, 5]);
+        assert_eq!(data.positions[5].x, 1.0);
+        assert_eq!(data.normals[0].z, 1.0);
+        assert_eq!(data.normals[1].z, -1.0);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = stl(2, &TRIANGLES);
+        assert!(read_stl(&bytes[..bytes.len() - 1]).is_err());
+        assert!(read_stl(&stl(3, &TRIANGLES)).is_err());
+        assert!(read_stl(&bytes[..HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_ascii_files() {
+        let error = read_stl(b"solid cube\nfacet normal 0 0 1\nendsolid cube\n").unwrap_err();
+        assert!(error.contains("ASCII"), "{}", error);
+    }
+}
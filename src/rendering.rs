@@ -1,42 +1,156 @@
 use point::Point;
 use vector::Vector3;
-use scene::{Scene, Element, Sphere, Plane, Color, Intersection, SurfaceType};
-use std::f32;
+use camera::{FisheyeMapping, Projection};
+use scene::{AdaptiveSampling, Scene, Element, Sphere, Plane, Triangle, Cuboid, Disk, Cylinder, Cone,
+             Torus, Instance, Csg, CsgOperation, Color, Intersection, SurfaceType};
+use mesh::Mesh;
+use sdf::Sdf;
+use heightfield::Heightfield;
+use matrix::Matrix44;
+use sampling::{disk_sample, pixel_sample, pixel_seed, shutter_sample};
+use bvh::BoundingBox;
+use std::{f32, f64};
 
 #[derive(Debug)]
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector3,
+    /// The moment within the camera's shutter interval at which this ray is traced.
+    pub time: f64,
+}
+
+/// The scene as seen through its camera, with what every primary ray needs worked out once per
+/// render.
+pub struct View<'a> {
+    pub scene: &'a Scene,
+    camera_to_world: Matrix44,
+}
+impl<'a> View<'a> {
+    pub fn new(scene: &'a Scene) -> View<'a> {
+        View {
+            scene: scene,
+            camera_to_world: scene.camera.camera_to_world(),
+        }
+    }
 }
 
 impl Ray {
-    pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Ray {
-        assert!(scene.width >= scene.height);
-        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
-        let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let sensor_x = ((((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) *
-                       fov_adjustment;
-        let sensor_y = (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
+    /// Creates the ray through a point within the given pixel, as the `sample`th of `count`
+    /// rays traced for that pixel. The point is offset from the pixel's top-left corner by
+    /// fractions of a pixel. Returns `None` if the point lies outside the area covered by the
+    /// camera's projection.
+    pub fn create_prime(x: u32,
+                        y: u32,
+                        (offset_x, offset_y): (f64, f64),
+                        sample: u32,
+                        count: u32,
+                        view: &View)
+                        -> Option<Ray> {
+        let scene = view.scene;
+        let camera = &scene.camera;
+        let ndc_x = ((x as f64 + offset_x) / scene.width as f64) * 2.0 - 1.0;
+        let ndc_y = 1.0 - ((y as f64 + offset_y) / scene.height as f64) * 2.0;
+        let seed = pixel_seed(x, y);
 
-        Ray {
-            origin: Point::zero(),
-            direction: Vector3 {
-                    x: sensor_x,
-                    y: sensor_y,
+        let (origin, direction) = match camera.projection {
+            Projection::Perspective => {
+                let (sensor_width, sensor_height) = camera.sensor_size(scene.width,
+                                                                       scene.height);
+                let sensor_x = ndc_x * sensor_width + camera.lens_shift;
+                let sensor_y = ndc_y * sensor_height;
+
+                //Every ray through this pixel converges on the same point of the focal plane,
+                //but leaves from a different point on the lens.
+                let (lens_x, lens_y) = disk_sample(seed, sample, count);
+                let lens_point = Point {
+                    x: lens_x * camera.aperture,
+                    y: lens_y * camera.aperture,
+                    z: 0.0,
+                };
+                let focus_point = Point {
+                    x: sensor_x * camera.focal_distance,
+                    y: sensor_y * camera.focal_distance,
+                    z: -camera.focal_distance,
+                };
+                (lens_point, focus_point - lens_point)
+            }
+            Projection::Orthographic { view_width } => {
+                let half_width = view_width / 2.0;
+                let half_height = half_width * scene.height as f64 / scene.width as f64;
+                let origin = Point {
+                    x: ndc_x * half_width,
+                    y: ndc_y * half_height,
+                    z: 0.0,
+                };
+                let direction = Vector3 {
+                    x: 0.0,
+                    y: 0.0,
                     z: -1.0,
+                };
+                (origin, direction)
+            }
+            Projection::Equirectangular => {
+                let longitude = ndc_x * f64::consts::PI;
+                let latitude = ndc_y * f64::consts::FRAC_PI_2;
+                let direction = Vector3 {
+                    x: latitude.cos() * longitude.sin(),
+                    y: latitude.sin(),
+                    z: -latitude.cos() * longitude.cos(),
+                };
+                (Point::zero(), direction)
+            }
+            Projection::Fisheye { mapping, fov } => {
+                let min_dimension = scene.width.min(scene.height) as f64;
+                let image_x = ndc_x * scene.width as f64 / min_dimension;
+                let image_y = ndc_y * scene.height as f64 / min_dimension;
+                let radius = (image_x * image_x + image_y * image_y).sqrt();
+                if radius > 1.0 {
+                    return None;
                 }
-                .normalize(),
-        }
+
+                let half_fov = fov.to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => radius * half_fov,
+                    FisheyeMapping::Equisolid => 2.0 * (radius * (half_fov / 2.0).sin()).asin(),
+                };
+                let phi = image_y.atan2(image_x);
+                let direction = Vector3 {
+                    x: theta.sin() * phi.cos(),
+                    y: theta.sin() * phi.sin(),
+                    z: -theta.cos(),
+                };
+                (Point::zero(), direction)
+            }
+            Projection::Cylindrical { fov } => {
+                let half_fov = fov.to_radians() / 2.0;
+                let longitude = ndc_x * half_fov;
+                let direction = Vector3 {
+                    x: longitude.sin(),
+                    y: ndc_y * half_fov * scene.height as f64 / scene.width as f64,
+                    z: -longitude.cos(),
+                };
+                (Point::zero(), direction)
+            }
+        };
+
+        let camera_to_world = view.camera_to_world;
+        Some(Ray {
+            origin: camera_to_world * origin,
+            direction: (camera_to_world * direction).normalize(),
+            time: camera.shutter.time(shutter_sample(seed, sample, count)),
+        })
     }
 
     pub fn create_reflection(normal: Vector3,
                              incident: Vector3,
                              intersection: Point,
-                             bias: f64)
+                             bias: f64,
+                             time: f64)
                              -> Ray {
         Ray {
             origin: intersection + (normal * bias),
             direction: incident - (2.0 * incident.dot(&normal) * normal),
+            time: time,
         }
     }
 
@@ -44,7 +158,8 @@ impl Ray {
                                incident: Vector3,
                                intersection: Point,
                                bias: f64,
-                               index: f32)
+                               index: f32,
+                               time: f64)
                                -> Option<Ray> {
         let mut ref_n = normal;
         let mut eta_t = index as f64;
@@ -68,12 +183,14 @@ impl Ray {
             Some(Ray {
                 origin: intersection + (ref_n * -bias),
                 direction: (incident + i_dot_n * ref_n) * eta - ref_n * k.sqrt(),
+                time: time,
             })
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[repr(C)]
 pub struct TextureCoords {
     pub x: f32,
     pub y: f32,
@@ -82,35 +199,139 @@ pub struct TextureCoords {
 pub trait Intersectable {
     fn intersect(&self, ray: &Ray) -> Option<f64>;
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3;
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords;
+    fn surface_normal(&self, hit_point: &Point, time: f64) -> Vector3;
+    fn texture_coords(&self, hit_point: &Point, time: f64) -> TextureCoords;
+
+    /// Returns a box the shape lies within, or `None` if it is unbounded.
+    fn bounding_box(&self) -> Option<BoundingBox>;
+
+    /// Finds every stretch of the ray that lies inside the shape, as entry and exit distances
+    /// in order along the ray. These may lie behind the ray's origin or be infinite. Shapes
+    /// without an inside have none.
+    fn intervals(&self, _: &Ray) -> Vec<(f64, f64)> {
+        vec![]
+    }
 }
 
-impl Intersectable for Element {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
+impl Element {
+    /// Returns a box the element lies within, or `None` if it is unbounded.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
         match *self {
-            Element::Sphere(ref s) => s.intersect(ray),
-            Element::Plane(ref p) => p.intersect(ray),
+            Element::Sphere(ref s) => s.bounding_box(),
+            Element::Plane(ref p) => p.bounding_box(),
+            Element::Triangle(ref t) => t.bounding_box(),
+            Element::Box(ref b) => b.bounding_box(),
+            Element::Disk(ref d) => d.bounding_box(),
+            Element::Cylinder(ref c) => c.revolved().bounding_box(),
+            Element::Cone(ref c) => c.revolved().bounding_box(),
+            Element::Torus(ref t) => t.bounding_box(),
+            Element::Mesh(ref m) => Some(BoundingBox::from_points(&m.data.positions)),
+            Element::Instance(ref i) => i.bounding_box(),
+            Element::Csg(ref c) => c.bounding_box(),
+            Element::Sdf(ref s) => s.bounding_box(),
+            Element::Heightfield(ref h) => h.bounding_box(),
         }
     }
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    /// Finds the nearest point at which the ray hits this element.
+    pub fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
         match *self {
-            Element::Sphere(ref s) => s.surface_normal(hit_point),
-            Element::Plane(ref p) => p.surface_normal(hit_point),
+            Element::Sphere(ref s) => trace_shape(s, self, ray),
+            Element::Plane(ref p) => face_ray(trace_shape(p, self, ray), ray, p.double_sided),
+            Element::Triangle(ref t) => {
+                face_ray(trace_shape(t, self, ray), ray, t.double_sided)
+            }
+            Element::Box(ref b) => trace_shape(b, self, ray),
+            Element::Disk(ref d) => face_ray(trace_shape(d, self, ray), ray, d.double_sided),
+            Element::Cylinder(ref c) => trace_shape(&c.revolved(), self, ray),
+            Element::Cone(ref c) => trace_shape(&c.revolved(), self, ray),
+            Element::Torus(ref t) => trace_shape(t, self, ray),
+            Element::Mesh(ref m) => face_ray(m.trace(self, ray), ray, m.double_sided),
+            Element::Instance(ref i) => i.trace(ray),
+            Element::Csg(ref c) => c.trace(ray),
+            Element::Sdf(ref s) => trace_shape(s, self, ray),
+            Element::Heightfield(ref h) => trace_shape(h, self, ray),
         }
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    /// Finds every stretch of the ray that lies inside this element, in order along the ray.
+    /// Elements that don't enclose a volume have none.
+    pub fn spans(&self, ray: &Ray) -> Vec<Span<'_>> {
         match *self {
-            Element::Sphere(ref s) => s.texture_coords(hit_point),
-            Element::Plane(ref p) => p.texture_coords(hit_point),
+            Element::Sphere(ref s) => shape_spans(s, self, ray),
+            Element::Plane(ref p) => shape_spans(p, self, ray),
+            Element::Triangle(ref t) => shape_spans(t, self, ray),
+            Element::Box(ref b) => shape_spans(b, self, ray),
+            Element::Disk(ref d) => shape_spans(d, self, ray),
+            Element::Cylinder(ref c) => shape_spans(&c.revolved(), self, ray),
+            Element::Cone(ref c) => shape_spans(&c.revolved(), self, ray),
+            Element::Torus(ref t) => shape_spans(t, self, ray),
+            Element::Mesh(_) => vec![],
+            Element::Instance(ref i) => i.spans(ray),
+            Element::Csg(ref c) => c.spans(ray),
+            Element::Sdf(_) | Element::Heightfield(_) => vec![],
         }
     }
 }
+
+/// A stretch of a ray inside a solid, with the hits where the ray enters and leaves it. An end
+/// is missing when the span carries on forever in that direction.
+pub struct Span<'a> {
+    pub entry: Option<Intersection<'a>>,
+    pub exit: Option<Intersection<'a>>,
+}
+
+fn shape_spans<'a, T: Intersectable>(shape: &T, element: &'a Element, ray: &Ray) -> Vec<Span<'a>> {
+    let hit = |distance: f64| if distance.is_finite() {
+        let hit_point = ray.origin + (ray.direction * distance);
+        Some(Intersection::new(distance,
+                               element,
+                               shape.surface_normal(&hit_point, ray.time),
+                               shape.texture_coords(&hit_point, ray.time)))
+    } else {
+        None
+    };
+    shape.intervals(ray)
+        .into_iter()
+        .map(|(entry, exit)| {
+            Span {
+                entry: hit(entry),
+                exit: hit(exit),
+            }
+        })
+        .collect()
+}
+
+fn trace_shape<'a, T: Intersectable>(shape: &T,
+                                      element: &'a Element,
+                                      ray: &Ray)
+                                      -> Option<Intersection<'a>> {
+    shape.intersect(ray).map(|distance| {
+        let hit_point = ray.origin + (ray.direction * distance);
+        Intersection::new(distance,
+                          element,
+                          shape.surface_normal(&hit_point, ray.time),
+                          shape.texture_coords(&hit_point, ray.time))
+    })
+}
+
+/// Turns the normal of a double-sided surface around when the ray hit its back, so it is shaded
+/// like the front.
+fn face_ray<'a>(intersection: Option<Intersection<'a>>,
+                ray: &Ray,
+                double_sided: bool)
+                -> Option<Intersection<'a>> {
+    intersection.map(|mut intersection| {
+        if double_sided && intersection.normal.dot(&ray.direction) > 0.0 {
+            intersection.normal = -intersection.normal;
+        }
+        intersection
+    })
+}
+
 impl Intersectable for Sphere {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
-        let l: Vector3 = self.center - ray.origin;
+        let l: Vector3 = self.center_at(ray.time) - ray.origin;
         let adj = l.dot(&ray.direction);
         let d2 = l.dot(&l) - (adj * adj);
         let radius2 = self.radius * self.radius;
@@ -133,12 +354,32 @@ impl Intersectable for Sphere {
         }
     }
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
-        (*hit_point - self.center).normalize()
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        //Where a moving sphere is depends on the time of the ray.
+        if self.velocity.length() > 0.0 {
+            return None;
+        }
+        Some(BoundingBox::around(&self.center, Vector3::from_one(self.radius)))
+    }
+
+    fn surface_normal(&self, hit_point: &Point, time: f64) -> Vector3 {
+        (*hit_point - self.center_at(time)).normalize()
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        let l: Vector3 = self.center_at(ray.time) - ray.origin;
+        let adj = l.dot(&ray.direction);
+        let d2 = l.dot(&l) - (adj * adj);
+        let radius2 = self.radius * self.radius;
+        if d2 > radius2 {
+            return vec![];
+        }
+        let thc = (radius2 - d2).sqrt();
+        vec![(adj - thc, adj + thc)]
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
-        let hit_vec = *hit_point - self.center;
+    fn texture_coords(&self, hit_point: &Point, time: f64) -> TextureCoords {
+        let hit_vec = *hit_point - self.center_at(time);
         TextureCoords {
             x: (1.0 + (hit_vec.z.atan2(hit_vec.x) as f32) / f32::consts::PI) * 0.5,
             y: (hit_vec.y / self.radius).acos() as f32 / f32::consts::PI,
@@ -149,8 +390,8 @@ impl Intersectable for Plane {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
         let normal = &self.normal;
         let denom = normal.dot(&ray.direction);
-        if denom > 1e-6 {
-            let v = self.origin - ray.origin;
+        if denom > 1e-6 || (self.double_sided && denom < -1e-6) {
+            let v = self.origin_at(ray.time) - ray.origin;
             let distance = v.dot(&normal) / denom;
             if distance >= 0.0 {
                 return Some(distance);
@@ -159,11 +400,33 @@ impl Intersectable for Plane {
         None
     }
 
-    fn surface_normal(&self, _: &Point) -> Vector3 {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        None
+    }
+
+    fn surface_normal(&self, _: &Point, _: f64) -> Vector3 {
         -self.normal
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        //The inside of a plane is the half-space its normal points into, behind its visible
+        //face.
+        let denom = self.normal.dot(&ray.direction);
+        let depth = (self.origin_at(ray.time) - ray.origin).dot(&self.normal);
+        if denom.abs() < 1e-12 {
+            if depth <= 0.0 {
+                vec![(f64::NEG_INFINITY, f64::INFINITY)]
+            } else {
+                vec![]
+            }
+        } else if denom > 0.0 {
+            vec![(depth / denom, f64::INFINITY)]
+        } else {
+            vec![(f64::NEG_INFINITY, depth / denom)]
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point, time: f64) -> TextureCoords {
         let mut x_axis = self.normal.cross(&Vector3 {
             x: 0.0,
             y: 0.0,
@@ -177,7 +440,7 @@ impl Intersectable for Plane {
             });
         }
         let y_axis = self.normal.cross(&x_axis);
-        let hit_vec = *hit_point - self.origin;
+        let hit_vec = *hit_point - self.origin_at(time);
 
         TextureCoords {
             x: hit_vec.dot(&x_axis) as f32,
@@ -186,6 +449,1039 @@ impl Intersectable for Plane {
     }
 }
 
+/// Intersects a ray with the front face of the triangle (v0, v1, v2) using the Möller-Trumbore
+/// algorithm. Returns the distance along the ray and the barycentric weights of v1 and v2.
+pub fn intersect_triangle(v0: &Point,
+                          v1: &Point,
+                          v2: &Point,
+                          double_sided: bool,
+                          ray: &Ray)
+                          -> Option<(f64, f64, f64)> {
+    let edge1 = *v1 - *v0;
+    let edge2 = *v2 - *v0;
+    let p = ray.direction.cross(&edge2);
+    let det = edge1.dot(&p);
+    //A negative determinant means the ray is hitting the back of the triangle.
+    if det.abs() < 1e-12 || (!double_sided && det < 0.0) {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let s = ray.origin - *v0;
+    let u = s.dot(&p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+    let q = s.cross(&edge1);
+    let v = ray.direction.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(&q) * inv_det;
+    if distance >= 0.0 {
+        Some((distance, u, v))
+    } else {
+        None
+    }
+}
+
+impl Intersectable for Triangle {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        intersect_triangle(&self.vertices[0],
+                           &self.vertices[1],
+                           &self.vertices[2],
+                           self.double_sided,
+                           ray)
+            .map(|(distance, _, _)| distance)
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(BoundingBox::from_points(&self.vertices))
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        match self.normals {
+            Some(ref normals) => {
+                let (u, v, w) = self.barycentric(hit_point);
+                (normals[0] * u + normals[1] * v + normals[2] * w).normalize()
+            }
+            None => self.face_normal(),
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: f64) -> TextureCoords {
+        let (u, v, w) = self.barycentric(hit_point);
+        match self.texture_coords {
+            Some(ref coords) => {
+                TextureCoords {
+                    x: coords[0].x * u as f32 + coords[1].x * v as f32 + coords[2].x * w as f32,
+                    y: coords[0].y * u as f32 + coords[1].y * v as f32 + coords[2].y * w as f32,
+                }
+            }
+            None => {
+                TextureCoords {
+                    x: v as f32,
+                    y: w as f32,
+                }
+            }
+        }
+    }
+}
+
+/// Finds which face of the box a point on its surface lies on. Returns the axis and side of that
+/// face, along with the point in the box's own frame scaled so the box spans -1 to 1.
+fn cuboid_face(cuboid: &Cuboid, hit_point: &Point) -> (usize, f64, [f64; 3]) {
+    let local = cuboid.orientation().transpose() * (*hit_point - cuboid.center());
+    let half_size = cuboid.half_size();
+    let scaled = [local.x / half_size.x, local.y / half_size.y, local.z / half_size.z];
+    let mut axis = 0;
+    for i in 1..3 {
+        if scaled[i].abs() > scaled[axis].abs() {
+            axis = i;
+        }
+    }
+    (axis, scaled[axis].signum(), scaled)
+}
+
+impl Cuboid {
+    /// Finds the stretch of the ray between the box's faces, including behind its origin.
+    fn slabs(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let to_local = self.orientation().transpose();
+        let origin = to_local * (ray.origin - self.center());
+        let direction = to_local * ray.direction;
+        let half_size = self.half_size();
+        let origin = [origin.x, origin.y, origin.z];
+        let direction = [direction.x, direction.y, direction.z];
+        let half_size = [half_size.x, half_size.y, half_size.z];
+
+        //Clip the ray against each pair of opposite faces in turn.
+        let mut near = f64::NEG_INFINITY;
+        let mut far = f64::INFINITY;
+        for axis in 0..3 {
+            if direction[axis].abs() < 1e-12 {
+                if origin[axis].abs() > half_size[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (-half_size[axis] - origin[axis]) / direction[axis];
+            let t1 = (half_size[axis] - origin[axis]) / direction[axis];
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+
+        if near > far {
+            None
+        } else {
+            Some((near, far))
+        }
+    }
+}
+
+impl Intersectable for Cuboid {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.slabs(ray).and_then(|(near, far)| nearest_ahead([near, far]))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        self.slabs(ray).into_iter().collect()
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let half_size = self.half_size();
+        let local = BoundingBox::around(&Point::zero(), half_size);
+        let corners: Vec<Point> = local.corners()
+            .iter()
+            .map(|corner| self.center() + self.orientation() * (*corner - Point::zero()))
+            .collect();
+        Some(BoundingBox::from_points(&corners))
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let (axis, side, _) = cuboid_face(self, hit_point);
+        let mut normal = [0.0; 3];
+        normal[axis] = side;
+        self.orientation() *
+        Vector3 {
+            x: normal[0],
+            y: normal[1],
+            z: normal[2],
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: f64) -> TextureCoords {
+        //Each face gets the whole texture, upright when seen from outside the box.
+        let (axis, side, p) = cuboid_face(self, hit_point);
+        let (u, v) = match (axis, side > 0.0) {
+            (0, true) => (-p[2], -p[1]),
+            (0, false) => (p[2], -p[1]),
+            (1, true) => (p[0], p[2]),
+            (1, false) => (p[0], -p[2]),
+            (2, true) => (p[0], -p[1]),
+            _ => (-p[0], -p[1]),
+        };
+        TextureCoords {
+            x: ((u + 1.0) * 0.5) as f32,
+            y: ((v + 1.0) * 0.5) as f32,
+        }
+    }
+}
+
+/// Picks the nearest of several distances along a ray, ignoring any behind its origin.
+fn nearest_ahead<I: IntoIterator<Item = f64>>(distances: I) -> Option<f64> {
+    distances.into_iter().filter(|&t| t >= 0.0).fold(None, |nearest, t| match nearest {
+        Some(n) if n <= t => Some(n),
+        _ => Some(t),
+    })
+}
+
+/// Builds a pair of unit vectors perpendicular to `normal` and to each other.
+fn perpendicular_axes(normal: &Vector3) -> (Vector3, Vector3) {
+    let mut x_axis = normal.cross(&Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    });
+    if x_axis.length() < 1e-6 {
+        x_axis = normal.cross(&Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        });
+    }
+    let x_axis = x_axis.normalize();
+    (x_axis, normal.cross(&x_axis))
+}
+
+/// Maps a point on a flat circle onto the whole texture.
+fn circle_texture_coords(offset: &Vector3, normal: &Vector3, radius: f64) -> TextureCoords {
+    let (x_axis, y_axis) = perpendicular_axes(normal);
+    TextureCoords {
+        x: (0.5 + offset.dot(&x_axis) / (2.0 * radius)) as f32,
+        y: (0.5 - offset.dot(&y_axis) / (2.0 * radius)) as f32,
+    }
+}
+
+/// Finds the box around a circle, which reaches less far along axes closer to its normal.
+fn disk_bounds(center: &Point, normal: &Vector3, radius: f64) -> BoundingBox {
+    let reach = |n: f64| radius * (1.0 - n * n).max(0.0).sqrt();
+    BoundingBox::around(center,
+                        Vector3 {
+                            x: reach(normal.x),
+                            y: reach(normal.y),
+                            z: reach(normal.z),
+                        })
+}
+
+impl Intersectable for Disk {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let denom = self.normal.dot(&ray.direction);
+        if denom.abs() < 1e-6 || (!self.double_sided && denom < 0.0) {
+            return None;
+        }
+        let distance = (self.center - ray.origin).dot(&self.normal) / denom;
+        if distance < 0.0 {
+            return None;
+        }
+        let hit_point = ray.origin + ray.direction * distance;
+        let offset = hit_point - self.center;
+        if offset.dot(&offset) > self.radius * self.radius {
+            return None;
+        }
+        Some(distance)
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(disk_bounds(&self.center, &self.normal, self.radius))
+    }
+
+    fn surface_normal(&self, _: &Point, _: f64) -> Vector3 {
+        -self.normal
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: f64) -> TextureCoords {
+        circle_texture_coords(&(*hit_point - self.center), &self.normal, self.radius)
+    }
+}
+
+/// A surface of revolution around `axis` whose radius changes linearly from `base_radius` at
+/// the base to `top_radius` at `height`, optionally closed off at either end. Cylinders and cones
+/// are both traced as one of these.
+struct Revolved {
+    base: Point,
+    axis: Vector3,
+    base_radius: f64,
+    top_radius: f64,
+    height: f64,
+    capped: bool,
+}
+
+enum RevolvedPart {
+    Side,
+    Bottom,
+    Top,
+}
+
+impl Cylinder {
+    fn revolved(&self) -> Revolved {
+        Revolved {
+            base: self.base,
+            axis: self.axis,
+            base_radius: self.radius,
+            top_radius: self.radius,
+            height: self.height,
+            capped: self.capped,
+        }
+    }
+}
+
+impl Cone {
+    fn revolved(&self) -> Revolved {
+        Revolved {
+            base: self.base,
+            axis: self.axis,
+            base_radius: self.radius,
+            top_radius: 0.0,
+            height: self.height,
+            capped: self.capped,
+        }
+    }
+}
+
+impl Revolved {
+    fn slope(&self) -> f64 {
+        (self.top_radius - self.base_radius) / self.height
+    }
+
+    /// Splits a point into its height along the axis and its offset from the axis.
+    fn local(&self, point: &Point) -> (f64, Vector3) {
+        let offset = *point - self.base;
+        let height = offset.dot(&self.axis);
+        (height, offset - self.axis * height)
+    }
+
+    fn part(&self, hit_point: &Point) -> RevolvedPart {
+        let (height, radial) = self.local(hit_point);
+        let side_distance = (radial.length() - (self.base_radius + self.slope() * height)).abs();
+        if self.capped && self.base_radius > 0.0 && height.abs() < side_distance {
+            RevolvedPart::Bottom
+        } else if self.capped && self.top_radius > 0.0 &&
+                  (self.height - height).abs() < side_distance {
+            RevolvedPart::Top
+        } else {
+            RevolvedPart::Side
+        }
+    }
+
+    /// Finds where the ray crosses the sloped side, including behind its origin.
+    fn intersect_side(&self, ray: &Ray) -> [Option<f64>; 2] {
+        let (origin_height, origin_radial) = self.local(&ray.origin);
+        let direction_height = ray.direction.dot(&self.axis);
+        let direction_radial = ray.direction - self.axis * direction_height;
+
+        //The radius along the ray is start_radius + t * radius_change; solve for where the
+        //ray's distance from the axis matches it.
+        let slope = self.slope();
+        let start_radius = self.base_radius + slope * origin_height;
+        let radius_change = slope * direction_height;
+        let a = direction_radial.dot(&direction_radial) - radius_change * radius_change;
+        let b = 2.0 * (origin_radial.dot(&direction_radial) - start_radius * radius_change);
+        let c = origin_radial.dot(&origin_radial) - start_radius * start_radius;
+
+        let roots = if a.abs() < 1e-12 {
+            if b.abs() < 1e-12 {
+                return [None, None];
+            }
+            [Some(-c / b), None]
+        } else {
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                return [None, None];
+            }
+            let root = discriminant.sqrt();
+            [Some((-b - root) / (2.0 * a)), Some((-b + root) / (2.0 * a))]
+        };
+
+        let within_height = |t: f64| {
+            let height = origin_height + t * direction_height;
+            height >= 0.0 && height <= self.height
+        };
+        [roots[0].filter(|&t| within_height(t)), roots[1].filter(|&t| within_height(t))]
+    }
+
+    /// Finds where the ray crosses the cap at the given height, including behind its origin.
+    fn intersect_cap(&self, ray: &Ray, height: f64, radius: f64) -> Option<f64> {
+        let denom = self.axis.dot(&ray.direction);
+        if !self.capped || radius <= 0.0 || denom.abs() < 1e-12 {
+            return None;
+        }
+        let center = self.base + self.axis * height;
+        let distance = (center - ray.origin).dot(&self.axis) / denom;
+        let offset = (ray.origin + ray.direction * distance) - center;
+        if offset.dot(&offset) > radius * radius {
+            return None;
+        }
+        Some(distance)
+    }
+}
+
+impl Intersectable for Revolved {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let side = self.intersect_side(ray);
+        let candidates = [side[0],
+                          side[1],
+                          self.intersect_cap(ray, 0.0, self.base_radius),
+                          self.intersect_cap(ray, self.height, self.top_radius)];
+        nearest_ahead(candidates.iter().filter_map(|&t| t))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        //A capped cylinder or cone is convex, so the ray is inside between its first and last
+        //crossings of the surface.
+        if !self.capped {
+            return vec![];
+        }
+        let side = self.intersect_side(ray);
+        let hits: Vec<f64> = [side[0],
+                              side[1],
+                              self.intersect_cap(ray, 0.0, self.base_radius),
+                              self.intersect_cap(ray, self.height, self.top_radius)]
+            .iter()
+            .filter_map(|&t| t)
+            .collect();
+        if hits.len() < 2 {
+            return vec![];
+        }
+        let entry = hits.iter().cloned().fold(f64::INFINITY, f64::min);
+        let exit = hits.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        vec![(entry, exit)]
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let top = self.base + self.axis * self.height;
+        let bounds = disk_bounds(&self.base, &self.axis, self.base_radius);
+        Some(bounds.union(&disk_bounds(&top, &self.axis, self.top_radius)))
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        match self.part(hit_point) {
+            RevolvedPart::Bottom => -self.axis,
+            RevolvedPart::Top => self.axis,
+            RevolvedPart::Side => {
+                let (_, radial) = self.local(hit_point);
+                (radial.normalize() - self.axis * self.slope()).normalize()
+            }
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: f64) -> TextureCoords {
+        let (height, radial) = self.local(hit_point);
+        match self.part(hit_point) {
+            RevolvedPart::Bottom => circle_texture_coords(&radial, &-self.axis, self.base_radius),
+            RevolvedPart::Top => circle_texture_coords(&radial, &self.axis, self.top_radius),
+            RevolvedPart::Side => {
+                //Wrap the texture once around the axis, with its top edge at the top.
+                let (x_axis, y_axis) = perpendicular_axes(&self.axis);
+                let angle = radial.dot(&y_axis).atan2(radial.dot(&x_axis));
+                TextureCoords {
+                    x: ((1.0 + angle / f64::consts::PI) * 0.5) as f32,
+                    y: (1.0 - height / self.height) as f32,
+                }
+            }
+        }
+    }
+}
+
+const EQUATION_EPSILON: f64 = 1e-9;
+
+fn is_zero(value: f64) -> bool {
+    value.abs() < EQUATION_EPSILON
+}
+
+/// Finds the real roots of x^2 + px + q = 0.
+fn solve_normalized_quadratic(p: f64, q: f64) -> Vec<f64> {
+    let half_p = p / 2.0;
+    let discriminant = half_p * half_p - q;
+    if is_zero(discriminant) {
+        vec![-half_p]
+    } else if discriminant < 0.0 {
+        vec![]
+    } else {
+        let root = discriminant.sqrt();
+        vec![-half_p - root, -half_p + root]
+    }
+}
+
+/// Finds the real roots of x^3 + ax^2 + bx + c = 0 using Cardano's method.
+fn solve_normalized_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    //Substitute x = y - a/3 to eliminate the quadratic term: y^3 + 3py + 2q = 0
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + c) / 2.0;
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let roots = if is_zero(discriminant) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        //Three real roots; use the trigonometric form to avoid complex arithmetic.
+        let phi = (-q / (-cb_p).sqrt()).max(-1.0).min(1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![t * phi.cos(),
+             -t * (phi + f64::consts::FRAC_PI_3).cos(),
+             -t * (phi - f64::consts::FRAC_PI_3).cos()]
+    } else {
+        let root = discriminant.sqrt();
+        vec![(root - q).cbrt() - (root + q).cbrt()]
+    };
+
+    let shift = a / 3.0;
+    roots.into_iter().map(|y| y - shift).collect()
+}
+
+/// Finds the real roots of c4 x^4 + c3 x^3 + c2 x^2 + c1 x + c0 = 0 using Ferrari's method. Each
+/// root is polished with a few Newton steps, since the closed form loses a lot of precision.
+fn solve_quartic(c4: f64, c3: f64, c2: f64, c1: f64, c0: f64) -> Vec<f64> {
+    let (a, b, c, d) = (c3 / c4, c2 / c4, c1 / c4, c0 / c4);
+
+    //Substitute x = y - a/4 to eliminate the cubic term: y^4 + py^2 + qy + r = 0
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + c;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * c / 4.0 + d;
+
+    let mut roots = if is_zero(r) {
+        //No constant term, so y = 0 is a root and the rest come from y^3 + py + q = 0
+        let mut roots = solve_normalized_cubic(0.0, p, q);
+        roots.push(0.0);
+        roots
+    } else {
+        //Take one root of the resolvent cubic and use it to split the quartic into two
+        //quadratics.
+        let z = solve_normalized_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0)[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        if u < -EQUATION_EPSILON || v < -EQUATION_EPSILON {
+            return vec![];
+        }
+        let u = u.max(0.0).sqrt();
+        let v = if q < 0.0 { -v.max(0.0).sqrt() } else { v.max(0.0).sqrt() };
+
+        let mut roots = solve_normalized_quadratic(v, z - u);
+        roots.extend(solve_normalized_quadratic(-v, z + u));
+        roots
+    };
+
+    let shift = a / 4.0;
+    for root in roots.iter_mut() {
+        let mut x = *root - shift;
+        for _ in 0..3 {
+            let value = (((c4 * x + c3) * x + c2) * x + c1) * x + c0;
+            let slope = ((4.0 * c4 * x + 3.0 * c3) * x + 2.0 * c2) * x + c1;
+            if slope == 0.0 {
+                break;
+            }
+            x -= value / slope;
+        }
+        *root = x;
+    }
+    roots
+}
+
+impl Torus {
+    /// Splits a point into its height along the axis and its offset from the axis.
+    fn local(&self, point: &Point) -> (f64, Vector3) {
+        let offset = *point - self.center;
+        let height = offset.dot(&self.axis);
+        (height, offset - self.axis * height)
+    }
+}
+
+impl Torus {
+    /// Finds every distance at which the ray crosses the surface, including behind its origin.
+    fn crossings(&self, ray: &Ray) -> Vec<f64> {
+        //Start the ray at the torus's bounding sphere; the quartic is badly conditioned when
+        //the origin is far away.
+        let bound = self.major_radius + self.minor_radius;
+        let to_center = self.center - ray.origin;
+        let adj = to_center.dot(&ray.direction);
+        let d2 = to_center.dot(&to_center) - adj * adj;
+        if d2 > bound * bound {
+            return vec![];
+        }
+        let start = adj - (bound * bound - d2).sqrt();
+
+        let origin = ray.origin + ray.direction * start - self.center;
+        let height = origin.dot(&self.axis);
+        let direction_height = ray.direction.dot(&self.axis);
+        let major2 = self.major_radius * self.major_radius;
+        let minor2 = self.minor_radius * self.minor_radius;
+        let e = origin.dot(&origin) - major2 - minor2;
+        let f = origin.dot(&ray.direction);
+        let four_major2 = 4.0 * major2;
+
+        let roots = solve_quartic(1.0,
+                                  4.0 * f,
+                                  2.0 * e + 4.0 * f * f + four_major2 * direction_height *
+                                                          direction_height,
+                                  4.0 * f * e + 2.0 * four_major2 * height * direction_height,
+                                  e * e - four_major2 * (minor2 - height * height));
+        roots.into_iter().map(|t| t + start).collect()
+    }
+}
+
+impl Intersectable for Torus {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        nearest_ahead(self.crossings(ray))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        let mut crossings = self.crossings(ray);
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        //A ray grazing the tube gives a repeated root; only whole pairs enclose the inside.
+        crossings.chunks(2).filter(|pair| pair.len() == 2).map(|pair| (pair[0], pair[1])).collect()
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let ring = disk_bounds(&self.center, &self.axis, self.major_radius);
+        Some(ring.expand(self.minor_radius))
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let (height, radial) = self.local(hit_point);
+        let ring = radial.normalize() * self.major_radius;
+        (radial - ring + self.axis * height).normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: f64) -> TextureCoords {
+        //U runs around the axis and V runs around the tube.
+        let (height, radial) = self.local(hit_point);
+        let (x_axis, y_axis) = perpendicular_axes(&self.axis);
+        let around_axis = radial.dot(&y_axis).atan2(radial.dot(&x_axis));
+        let around_tube = height.atan2(radial.length() - self.major_radius);
+        TextureCoords {
+            x: ((1.0 + around_axis / f64::consts::PI) * 0.5) as f32,
+            y: ((1.0 + around_tube / f64::consts::PI) * 0.5) as f32,
+        }
+    }
+}
+
+impl Instance {
+    /// Moves a ray into the wrapped element's space. Scaling changes the length of the ray's
+    /// direction, so this also returns how much distances along the ray are scaled by.
+    fn object_ray(&self, ray: &Ray) -> (Ray, f64) {
+        let to_object = self.to_object();
+        let direction = to_object * ray.direction;
+        let object_ray = Ray {
+            origin: to_object * ray.origin,
+            direction: direction.normalize(),
+            time: ray.time,
+        };
+        (object_ray, direction.length())
+    }
+
+    /// Moves a hit on the wrapped element back out into world space.
+    fn world_intersection<'a>(&'a self,
+                              mut intersection: Intersection<'a>,
+                              scale: f64)
+                              -> Intersection<'a> {
+        intersection.distance /= scale;
+        intersection.normal = (self.to_object().transpose() * intersection.normal).normalize();
+        if let Some(ref material) = self.material {
+            intersection.material = material;
+        }
+        intersection
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let to_world = self.to_object().inverse();
+        self.element().bounding_box().map(|bounds| {
+            let corners: Vec<Point> = bounds.corners().iter().map(|c| to_world * *c).collect();
+            BoundingBox::from_points(&corners)
+        })
+    }
+
+    fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let (object_ray, scale) = self.object_ray(ray);
+        self.element().trace(&object_ray).map(|i| self.world_intersection(i, scale))
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span<'_>> {
+        let (object_ray, scale) = self.object_ray(ray);
+        self.element()
+            .spans(&object_ray)
+            .into_iter()
+            .map(|span| {
+                Span {
+                    entry: span.entry.map(|i| self.world_intersection(i, scale)),
+                    exit: span.exit.map(|i| self.world_intersection(i, scale)),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Csg {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let (left, right) = (self.left.bounding_box(), self.right.bounding_box());
+        match self.operation {
+            CsgOperation::Union => {
+                match (left, right) {
+                    (Some(left), Some(right)) => Some(left.union(&right)),
+                    _ => None,
+                }
+            }
+            CsgOperation::Intersection => {
+                match (left, right) {
+                    (Some(left), Some(right)) => Some(left.intersection(&right)),
+                    (bounds, None) | (None, bounds) => bounds,
+                }
+            }
+            CsgOperation::Difference => left,
+        }
+    }
+
+    fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        //Spans are in order along the ray, so the first boundary ahead of the origin is the
+        //nearest hit.
+        self.spans(ray)
+            .into_iter()
+            .flat_map(|span| vec![span.entry, span.exit])
+            .filter_map(|boundary| boundary)
+            .find(|boundary| boundary.distance >= 0.0)
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span<'_>> {
+        self.combine(self.left.spans(ray), self.right.spans(ray))
+    }
+
+    /// Works out where a ray is inside the combination from where it is inside each element.
+    fn combine<'a>(&'a self, left: Vec<Span<'a>>, right: Vec<Span<'a>>) -> Vec<Span<'a>> {
+        let flip_right = self.operation == CsgOperation::Difference;
+        //A span with a NaN end, such as one from an instance scaled down to nothing, can't be
+        //placed along the ray, so it is left out.
+        let placeable = |span: &Span| {
+            [&span.entry, &span.exit]
+                .iter()
+                .all(|boundary| boundary.as_ref().map_or(true, |i| !i.distance.is_nan()))
+        };
+        let mut boundaries = vec![];
+        for span in left.into_iter().filter(&placeable) {
+            boundaries.push((span.entry, true, true));
+            boundaries.push((span.exit, true, false));
+        }
+        for span in right.into_iter().filter(&placeable) {
+            boundaries.push((span.entry, false, true));
+            boundaries.push((span.exit, false, false));
+        }
+        let distance = |boundary: &(Option<Intersection>, bool, bool)| match boundary.0 {
+            Some(ref intersection) => intersection.distance,
+            None if boundary.2 => f64::NEG_INFINITY,
+            None => f64::INFINITY,
+        };
+        boundaries.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+
+        //Sweep along the ray, keeping track of which elements it is inside; every time that
+        //changes whether it is inside the combination, a boundary of the result is crossed.
+        let mut in_left = false;
+        let mut in_right = false;
+        let mut entry = None;
+        let mut spans = vec![];
+        for (mut boundary, is_left, entering) in boundaries {
+            let was_inside = self.contains(in_left, in_right);
+            if is_left {
+                in_left = entering;
+            } else {
+                in_right = entering;
+            }
+            let inside = self.contains(in_left, in_right);
+            if inside == was_inside {
+                continue;
+            }
+
+            if let Some(ref mut intersection) = boundary {
+                //Surfaces cut out of the left element face into the right one.
+                if flip_right && !is_left {
+                    intersection.normal = -intersection.normal;
+                }
+                if let Some(ref material) = self.material {
+                    intersection.material = material;
+                }
+            }
+            if inside {
+                entry = Some(boundary);
+            } else {
+                spans.push(Span {
+                    entry: entry.take().unwrap_or(None),
+                    exit: boundary,
+                });
+            }
+        }
+        spans
+    }
+
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match self.operation {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+const SDF_MAX_STEPS: u32 = 512;
+/// How close a ray must come to an SDF surface to hit it, per unit of distance travelled.
+const SDF_EPSILON: f64 = 1e-4;
+
+impl Intersectable for Sdf {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        //Step along the ray by the distance to the nearest surface, which can never overshoot
+        //it. Rays that start on the surface, such as shadow and reflection rays, must get clear
+        //of it before a hit counts.
+        let mut distance = 0.0;
+        let mut clear = false;
+        for _ in 0..SDF_MAX_STEPS {
+            let step = self.shape.distance(&(ray.origin + ray.direction * distance)).abs();
+            let threshold = SDF_EPSILON * distance.max(1.0);
+            if step >= threshold {
+                clear = true;
+            } else if clear {
+                return Some(distance);
+            }
+            distance += step.max(threshold);
+            if distance > self.max_distance {
+                break;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.shape.bounding_box()
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        //The gradient of the distance field points away from the surface.
+        let h = SDF_EPSILON * 10.0;
+        let sample = |x: f64, y: f64, z: f64| {
+            self.shape.distance(&(*hit_point + Vector3 { x: x, y: y, z: z }))
+        };
+        Vector3 {
+                x: sample(h, 0.0, 0.0) - sample(-h, 0.0, 0.0),
+                y: sample(0.0, h, 0.0) - sample(0.0, -h, 0.0),
+                z: sample(0.0, 0.0, h) - sample(0.0, 0.0, -h),
+            }
+            .normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point, time: f64) -> TextureCoords {
+        //There's no natural parameterization, so wrap the texture around the normal like a
+        //sphere's.
+        let normal = self.surface_normal(hit_point, time);
+        TextureCoords {
+            x: (1.0 + (normal.z.atan2(normal.x) as f32) / f32::consts::PI) * 0.5,
+            y: normal.y.max(-1.0).min(1.0).acos() as f32 / f32::consts::PI,
+        }
+    }
+}
+
+impl Heightfield {
+    /// Finds the grid cell a point lies over, along with how far across that cell it is in X
+    /// and Z.
+    fn cell_at(&self, point: &Point) -> (usize, usize, f64, f64) {
+        let grid_x = ((point.x - self.origin.x) / self.cell_width())
+            .max(0.0)
+            .min((self.data.columns - 1) as f64);
+        let grid_z = ((point.z - self.origin.z) / self.cell_depth())
+            .max(0.0)
+            .min((self.data.rows - 1) as f64);
+        let column = (grid_x.floor() as usize).min(self.data.columns - 2);
+        let row = (grid_z.floor() as usize).min(self.data.rows - 2);
+        (column, row, grid_x - column as f64, grid_z - row as f64)
+    }
+
+    /// Intersects the two triangles that make up a grid cell, split along the diagonal from
+    /// its lowest corner.
+    fn intersect_cell(&self, column: usize, row: usize, ray: &Ray) -> Option<f64> {
+        let v00 = self.vertex(column, row);
+        let v10 = self.vertex(column + 1, row);
+        let v01 = self.vertex(column, row + 1);
+        let v11 = self.vertex(column + 1, row + 1);
+        let first = intersect_triangle(&v00, &v11, &v10, false, ray).map(|(t, _, _)| t);
+        let second = intersect_triangle(&v00, &v01, &v11, false, ray).map(|(t, _, _)| t);
+        nearest_ahead(first.into_iter().chain(second))
+    }
+
+    /// Finds the stretch of the ray inside the terrain's bounding box.
+    fn bounds(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let min = [self.origin.x, self.origin.y, self.origin.z];
+        let max = [self.origin.x + self.width,
+                   self.origin.y + self.height_scale,
+                   self.origin.z + self.depth];
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+
+        let mut near: f64 = 0.0;
+        let mut far = f64::INFINITY;
+        for axis in 0..3 {
+            if direction[axis].abs() < 1e-12 {
+                if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (min[axis] - origin[axis]) / direction[axis];
+            let t1 = (max[axis] - origin[axis]) / direction[axis];
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        if near > far {
+            None
+        } else {
+            Some((near, far))
+        }
+    }
+}
+
+impl Intersectable for Heightfield {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (enter, exit) = match self.bounds(ray) {
+            Some(bounds) => bounds,
+            None => return None,
+        };
+
+        //Walk through the cells under the ray in order, so the first hit found is the nearest.
+        let start = ray.origin + ray.direction * enter;
+        let (column, row, _, _) = self.cell_at(&start);
+        let (mut column, mut row) = (column as isize, row as isize);
+        let step = |direction: f64, size: f64, start: f64, origin: f64, index: isize| {
+            if direction == 0.0 {
+                return (0, f64::INFINITY, f64::INFINITY);
+            }
+            let next_index = if direction > 0.0 { index + 1 } else { index };
+            let next = origin + next_index as f64 * size;
+            (direction.signum() as isize,
+             enter + (next - start) / direction,
+             size / direction.abs())
+        };
+        let (step_x, mut next_x, delta_x) =
+            step(ray.direction.x, self.cell_width(), start.x, self.origin.x, column);
+        let (step_z, mut next_z, delta_z) =
+            step(ray.direction.z, self.cell_depth(), start.z, self.origin.z, row);
+
+        let columns = self.data.columns as isize - 1;
+        let rows = self.data.rows as isize - 1;
+        while column >= 0 && column < columns && row >= 0 && row < rows {
+            if let Some(distance) = self.intersect_cell(column as usize, row as usize, ray) {
+                return Some(distance);
+            }
+            if next_x.min(next_z) > exit {
+                break;
+            }
+            if next_x < next_z {
+                column += step_x;
+                next_x += delta_x;
+            } else {
+                row += step_z;
+                next_z += delta_z;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let far_corner = self.origin +
+                         Vector3 {
+            x: self.width,
+            y: self.height_scale,
+            z: self.depth,
+        };
+        Some(BoundingBox::from_points(&[self.origin, far_corner]))
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let (column, row, x, z) = self.cell_at(hit_point);
+        let n00 = self.normal(column, row);
+        let n11 = self.normal(column + 1, row + 1);
+        let normal = if x >= z {
+            n00 * (1.0 - x) + self.normal(column + 1, row) * (x - z) + n11 * z
+        } else {
+            n00 * (1.0 - z) + self.normal(column, row + 1) * (z - x) + n11 * x
+        };
+        normal.normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: f64) -> TextureCoords {
+        //The texture is draped over the whole terrain, lined up with the height image.
+        TextureCoords {
+            x: ((hit_point.x - self.origin.x) / self.width) as f32,
+            y: ((hit_point.z - self.origin.z) / self.depth) as f32,
+        }
+    }
+}
+
+impl Mesh {
+    fn trace<'a>(&'a self, element: &'a Element, ray: &Ray) -> Option<Intersection<'a>> {
+        let data = &self.data;
+        let nearest = self.bvh.nearest(ray, |i| {
+            let face = &data.faces[i];
+            intersect_triangle(&data.positions[face.vertices[0]],
+                               &data.positions[face.vertices[1]],
+                               &data.positions[face.vertices[2]],
+                               self.double_sided,
+                               ray)
+                .map(|(distance, u, v)| (distance, (u, v, face)))
+        });
+
+        nearest.map(|(distance, (u, v, face))| {
+            let w = 1.0 - u - v;
+            let normal = match face.normals {
+                Some(n) => {
+                    (data.normals[n[0]] * w + data.normals[n[1]] * u + data.normals[n[2]] * v)
+                        .normalize()
+                }
+                None => data.face_normal(face),
+            };
+            let texture_coords = match face.texture_coords {
+                Some(t) => {
+                    let (t0, t1, t2) = (data.texture_coords[t[0]],
+                                        data.texture_coords[t[1]],
+                                        data.texture_coords[t[2]]);
+                    let (u, v, w) = (u as f32, v as f32, w as f32);
+                    TextureCoords {
+                        x: t0.x * w + t1.x * u + t2.x * v,
+                        y: t0.y * w + t1.y * u + t2.y * v,
+                    }
+                }
+                None => {
+                    TextureCoords {
+                        x: u as f32,
+                        y: v as f32,
+                    }
+                }
+            };
+
+            let mut intersection = Intersection::new(distance, element, normal, texture_coords);
+            if let Some(material) = face.material {
+                intersection.material = &data.materials[material];
+            }
+            if !data.colors.is_empty() {
+                let (c0, c1, c2) = (data.colors[face.vertices[0]],
+                                    data.colors[face.vertices[1]],
+                                    data.colors[face.vertices[2]]);
+                intersection.vertex_color = Some(c0 * w as f32 + c1 * u as f32 + c2 * v as f32);
+            }
+            intersection
+        })
+    }
+}
+
 const BLACK: Color = Color {
     red: 0.0,
     green: 0.0,
@@ -193,11 +1489,11 @@ const BLACK: Color = Color {
 };
 
 fn shade_diffuse(scene: &Scene,
-                 element: &Element,
+                 intersection: &Intersection,
                  hit_point: Point,
-                 surface_normal: Vector3)
+                 time: f64)
                  -> Color {
-    let texture_coords = element.texture_coords(&hit_point);
+    let surface_normal = intersection.normal;
     let mut color = BLACK;
     for light in &scene.lights {
         let direction_to_light = light.direction_from(&hit_point);
@@ -205,6 +1501,7 @@ fn shade_diffuse(scene: &Scene,
         let shadow_ray = Ray {
             origin: hit_point + (surface_normal * scene.shadow_bias),
             direction: direction_to_light,
+            time: time,
         };
         let shadow_intersection = scene.trace(&shadow_ray);
         let in_light = shadow_intersection.is_none() ||
@@ -215,28 +1512,28 @@ fn shade_diffuse(scene: &Scene,
         } else {
             0.0
         };
-        let material = element.material();
+        let material = intersection.material;
         let light_power = (surface_normal.dot(&direction_to_light) as f32).max(0.0) *
                           light_intensity;
         let light_reflected = material.albedo / f32::consts::PI;
 
         let light_color = light.color() * light_power * light_reflected;
-        color = color + (material.coloration.color(&texture_coords) * light_color);
+        color = color + (intersection.color() * light_color);
     }
     color.clamp()
 }
 
 fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32) -> Color {
     let hit = ray.origin + (ray.direction * intersection.distance);
-    let normal = intersection.element.surface_normal(&hit);
+    let normal = intersection.normal;
 
-    let material = intersection.element.material();
+    let material = intersection.material;
     match material.surface {
-        SurfaceType::Diffuse => shade_diffuse(scene, intersection.element, hit, normal),
+        SurfaceType::Diffuse => shade_diffuse(scene, intersection, hit, ray.time),
         SurfaceType::Reflective { reflectivity } => {
-            let mut color = shade_diffuse(scene, intersection.element, hit, normal);
+            let mut color = shade_diffuse(scene, intersection, hit, ray.time);
             let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias);
+                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias, ray.time);
             color = color * (1.0 - reflectivity);
             color = color + (cast_ray(scene, &reflection_ray, depth + 1) * reflectivity);
             color
@@ -244,18 +1541,22 @@ fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32)
         SurfaceType::Refractive { index, transparency } => {
             let mut refraction_color = BLACK;
             let kr = fresnel(ray.direction, normal, index) as f32;
-            let surface_color = material.coloration
-                .color(&intersection.element.texture_coords(&hit));
+            let surface_color = intersection.color();
 
             if kr < 1.0 {
                 let transmission_ray =
-                    Ray::create_transmission(normal, ray.direction, hit, scene.shadow_bias, index)
+                    Ray::create_transmission(normal,
+                                             ray.direction,
+                                             hit,
+                                             scene.shadow_bias,
+                                             index,
+                                             ray.time)
                         .unwrap();
                 refraction_color = cast_ray(scene, &transmission_ray, depth + 1);
             }
 
             let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias);
+                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias, ray.time);
             let reflection_color = cast_ray(scene, &reflection_ray, depth + 1);
             let mut color = reflection_color * kr + refraction_color * (1.0 - kr);
             color = color * transparency * surface_color;
@@ -295,3 +1596,373 @@ pub fn cast_ray(scene: &Scene, ray: &Ray, depth: u32) -> Color {
     intersection.map(|i| get_color(scene, &ray, &i, depth))
         .unwrap_or(BLACK)
 }
+
+/// Finds the color seen through a point within a pixel; see `Ray::create_prime`.
+pub fn render_sample(x: u32,
+                     y: u32,
+                     offset: (f64, f64),
+                     sample: u32,
+                     count: u32,
+                     view: &View)
+                     -> Color {
+    Ray::create_prime(x, y, offset, sample, count, view)
+        .map(|ray| cast_ray(view.scene, &ray, 0))
+        .unwrap_or(BLACK)
+}
+
+pub fn render_pixel(x: u32, y: u32, view: &View) -> Color {
+    let samples = view.scene.samples_per_pixel.max(1);
+    let seed = pixel_seed(x, y);
+    let mut color = BLACK;
+    for sample in 0..samples {
+        let offset = pixel_sample(seed, sample, samples);
+        color = color + render_sample(x, y, offset, sample, samples, view);
+    }
+    color * (1.0 / samples as f32)
+}
+
+/// Deepest an adaptively sampled pixel is ever split, which limits it to 87380 samples.
+const MAX_ADAPTIVE_DEPTH: u32 = 8;
+
+/// Renders a pixel that stood out from its neighbors. The centers of its four quarters are
+/// sampled, and any quarter is split into quarters again while their samples differ by more
+/// than the threshold, up to the maximum depth.
+pub fn render_pixel_adaptive(x: u32, y: u32, view: &View, settings: &AdaptiveSampling) -> Color {
+    let max_depth = settings.max_depth.min(MAX_ADAPTIVE_DEPTH);
+    if max_depth == 0 {
+        return render_sample(x, y, (0.5, 0.5), 0, 1, view);
+    }
+    let pixel = AdaptivePixel {
+        x: x,
+        y: y,
+        max_depth: max_depth,
+        threshold: settings.threshold,
+        //The most samples that could be taken, so each one gets its own lens and shutter
+        //position.
+        count: (4u32.pow(max_depth + 1) - 4) / 3,
+        view: view,
+    };
+    let mut sample = 0;
+    pixel.render_area((0.0, 0.0), 1.0, 1, &mut sample)
+}
+
+struct AdaptivePixel<'a> {
+    x: u32,
+    y: u32,
+    max_depth: u32,
+    threshold: f32,
+    count: u32,
+    view: &'a View<'a>,
+}
+
+impl<'a> AdaptivePixel<'a> {
+    /// Renders the square part of the pixel with its top-left corner at `(left, top)` and sides
+    /// `size` pixels long, at the given depth of splitting.
+    fn render_area(&self,
+                   (left, top): (f64, f64),
+                   size: f64,
+                   depth: u32,
+                   sample: &mut u32)
+                   -> Color {
+        let half = size / 2.0;
+        let quarters = [(left, top),
+                        (left + half, top),
+                        (left, top + half),
+                        (left + half, top + half)];
+        let mut colors = [BLACK; 4];
+        for (color, &(left, top)) in colors.iter_mut().zip(quarters.iter()) {
+            let center = (left + half / 2.0, top + half / 2.0);
+            *color = render_sample(self.x, self.y, center, *sample, self.count, self.view);
+            *sample += 1;
+        }
+
+        let differs = colors.iter().any(|a| colors.iter().any(|b| a.contrast(b) > self.threshold));
+        if depth < self.max_depth && differs {
+            for (color, &quarter) in colors.iter_mut().zip(quarters.iter()) {
+                *color = self.render_area(quarter, half, depth + 1, sample);
+            }
+        }
+        colors.iter().fold(BLACK, |sum, &color| sum + color) * 0.25
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that the same roots were found as expected, allowing repeated roots to be
+    /// reported any number of times.
+    fn assert_roots(found: Vec<f64>, expected: &[f64]) {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+        for &root in &found {
+            assert!(expected.iter().any(|&e| close(e, root)),
+                    "found unexpected root {} in {:?}, expected {:?}",
+                    root,
+                    found,
+                    expected);
+        }
+        for &root in expected {
+            assert!(found.iter().any(|&f| close(f, root)),
+                    "missing root {} in {:?}",
+                    root,
+                    found);
+        }
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        assert_roots(solve_normalized_quadratic(-3.0, 2.0), &[1.0, 2.0]);
+        assert_roots(solve_normalized_quadratic(-6.0, 9.0), &[3.0]);
+        assert_roots(solve_normalized_quadratic(0.0, 1.0), &[]);
+    }
+
+    #[test]
+    fn cubic_roots() {
+        //(x - 1)(x - 2)(x - 3)
+        assert_roots(solve_normalized_cubic(-6.0, 11.0, -6.0), &[1.0, 2.0, 3.0]);
+        //(x - 1)(x^2 + x + 1)
+        assert_roots(solve_normalized_cubic(0.0, 0.0, -1.0), &[1.0]);
+        //(x - 1)^2 (x + 2)
+        assert_roots(solve_normalized_cubic(0.0, -3.0, 2.0), &[1.0, -2.0]);
+        //(x - 2)^3
+        assert_roots(solve_normalized_cubic(-6.0, 12.0, -8.0), &[2.0]);
+    }
+
+    #[test]
+    fn quartic_roots() {
+        //2(x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(solve_quartic(2.0, -20.0, 70.0, -100.0, 48.0),
+                     &[1.0, 2.0, 3.0, 4.0]);
+        //(x^2 + 1)(x - 1)(x + 3)
+        assert_roots(solve_quartic(1.0, 2.0, -2.0, 2.0, -3.0), &[1.0, -3.0]);
+        //x^2 (x - 1)(x + 1), which has no constant term
+        assert_roots(solve_quartic(1.0, 0.0, -1.0, 0.0, 0.0), &[0.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn quartic_repeated_roots() {
+        //(x - 1)^2 (x + 2)^2
+        assert_roots(solve_quartic(1.0, 2.0, -3.0, -4.0, 4.0), &[1.0, -2.0]);
+        //(x - 3)^4
+        assert_roots(solve_quartic(1.0, -12.0, 54.0, -108.0, 81.0), &[3.0]);
+    }
+
+    #[test]
+    fn quartic_without_real_roots() {
+        assert_roots(solve_quartic(1.0, 0.0, 0.0, 0.0, 1.0), &[]);
+        //(x^2 + 1)(x^2 + 4)
+        assert_roots(solve_quartic(1.0, 0.0, 5.0, 0.0, 4.0), &[]);
+        //(x^2 + 2x + 2)(x^2 - 4x + 5)
+        assert_roots(solve_quartic(1.0, -2.0, 1.0, 2.0, 10.0), &[]);
+    }
+
+    use scene::{Coloration, Material};
+    use heightfield::HeightfieldData;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn white() -> Material {
+        Material {
+            coloration: Coloration::Color(Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            }),
+            albedo: 0.18,
+            surface: SurfaceType::Diffuse,
+        }
+    }
+
+    fn sphere(z: f64, radius: f64) -> Box<Element> {
+        Box::new(Element::Sphere(Sphere {
+            center: Point { x: 0.0, y: 0.0, z: z },
+            radius: radius,
+            material: white(),
+            velocity: Vector3::zero(),
+        }))
+    }
+
+    fn csg(operation: CsgOperation, left: Box<Element>, right: Box<Element>) -> Element {
+        Element::Csg(Csg {
+            operation: operation,
+            left: left,
+            right: right,
+            material: None,
+        })
+    }
+
+    fn forward() -> Ray {
+        Ray {
+            origin: Point::zero(),
+            direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+            time: 0.0,
+        }
+    }
+
+    /// Traces straight down -Z through the combination, returning the distance and the Z part
+    /// of the normal at every boundary of the result.
+    fn boundaries(element: &Element) -> Vec<(f64, f64)> {
+        element.spans(&forward())
+            .into_iter()
+            .flat_map(|span| vec![span.entry, span.exit])
+            .map(|boundary| {
+                let hit = boundary.expect("span should be closed");
+                (hit.distance, hit.normal.z)
+            })
+            .collect()
+    }
+
+    fn assert_boundaries(element: &Element, expected: &[(f64, f64)]) {
+        let found = boundaries(element);
+        assert_eq!(found.len(), expected.len(), "found {:?}", found);
+        for (&(distance, normal), &(expected_distance, expected_normal)) in
+            found.iter().zip(expected) {
+            assert!((distance - expected_distance).abs() < 1e-9 &&
+                    (normal - expected_normal).abs() < 1e-9,
+                    "found {:?}, expected {:?}",
+                    found,
+                    expected);
+        }
+        let hit = element.trace(&forward()).map(|i| (i.distance, i.normal.z));
+        assert_eq!(hit.map(|h| h.0.round()), expected.first().map(|e| e.0));
+    }
+
+    //The left sphere covers 4 to 6 along the ray and the right one 5 to 7. Normals face back
+    //along the ray at entries and along it at exits.
+
+    #[test]
+    fn csg_union() {
+        let union = csg(CsgOperation::Union, sphere(-5.0, 1.0), sphere(-6.0, 1.0));
+        assert_boundaries(&union, &[(4.0, 1.0), (7.0, -1.0)]);
+    }
+
+    #[test]
+    fn csg_intersection() {
+        let intersection = csg(CsgOperation::Intersection, sphere(-5.0, 1.0), sphere(-6.0, 1.0));
+        assert_boundaries(&intersection, &[(5.0, 1.0), (6.0, -1.0)]);
+    }
+
+    #[test]
+    fn csg_difference() {
+        let difference = csg(CsgOperation::Difference, sphere(-5.0, 1.0), sphere(-6.0, 1.0));
+        assert_boundaries(&difference, &[(4.0, 1.0), (5.0, -1.0)]);
+
+        //Cutting from the far side leaves the exit on the inside of the right sphere.
+        let difference = csg(CsgOperation::Difference, sphere(-6.0, 1.0), sphere(-5.0, 1.0));
+        assert_boundaries(&difference, &[(6.0, 1.0), (7.0, -1.0)]);
+    }
+
+    #[test]
+    fn csg_difference_misses_when_the_left_element_is_inside_the_right() {
+        let difference = csg(CsgOperation::Difference, sphere(-5.0, 1.0), sphere(-5.0, 2.0));
+        assert_boundaries(&difference, &[]);
+    }
+
+    #[test]
+    fn csg_skips_spans_with_nan_ends() {
+        let union = csg(CsgOperation::Union, sphere(-5.0, 1.0), sphere(-6.0, 1.0));
+        let union = match union {
+            Element::Csg(ref c) => c,
+            _ => unreachable!(),
+        };
+        let ray = forward();
+        let mut broken = union.right.spans(&ray);
+        if let Some(ref mut entry) = broken[0].entry {
+            entry.distance = f64::NAN;
+        }
+        let distances: Vec<_> = union.combine(union.left.spans(&ray), broken)
+            .into_iter()
+            .map(|span| (span.entry.unwrap().distance, span.exit.unwrap().distance))
+            .collect();
+        assert_eq!(distances, vec![(4.0, 6.0)]);
+    }
+
+    /// A 2x2 cell terrain with corners from (0, 0, 0) to (2, 1, 2). It is flat apart from the
+    /// grid point at column 2, row 1, which is raised to 0.8.
+    fn terrain() -> Heightfield {
+        Heightfield {
+            path: PathBuf::new(),
+            origin: Point::zero(),
+            width: 2.0,
+            depth: 2.0,
+            height_scale: 1.0,
+            material: white(),
+            data: HeightfieldData {
+                columns: 3,
+                rows: 3,
+                heights: vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.8, 0.0, 0.0, 0.0],
+                normals: vec![],
+            },
+        }
+    }
+
+    fn hit_point(heightfield: &Heightfield, origin: Point, direction: Vector3) -> Option<Point> {
+        let ray = Ray {
+            origin: origin,
+            direction: direction.normalize(),
+            time: 0.0,
+        };
+        heightfield.intersect(&ray).map(|distance| ray.origin + ray.direction * distance)
+    }
+
+    fn assert_hit(found: Option<Point>, expected: Point) {
+        let found = found.expect("ray should hit the terrain");
+        assert!((found - expected).length() < 1e-9,
+                "found {:?}, expected {:?}",
+                found,
+                expected);
+    }
+
+    #[test]
+    fn heightfield_hit_within_a_cell() {
+        //Over the first triangle of the cell in column 1, row 0, a quarter of the way along
+        //the diagonal to the raised corner.
+        let found = hit_point(&terrain(),
+                              Point { x: 1.75, y: 5.0, z: 0.25 },
+                              Vector3 { x: 0.0, y: -1.0, z: 0.0 });
+        assert_hit(found, Point { x: 1.75, y: 0.2, z: 0.25 });
+
+        //The same cell's other triangle, where the height rises along X.
+        let found = hit_point(&terrain(),
+                              Point { x: 1.25, y: 5.0, z: 0.75 },
+                              Vector3 { x: 0.0, y: -1.0, z: 0.0 });
+        assert_hit(found, Point { x: 1.25, y: 0.2, z: 0.75 });
+    }
+
+    #[test]
+    fn heightfield_hit_from_outside_the_grid() {
+        //Comes in through the side at X = 0, passes over the flat first cell and meets the
+        //slope in the second.
+        let found = hit_point(&terrain(),
+                              Point { x: -1.0, y: 2.0, z: 0.25 },
+                              Vector3 { x: 2.75, y: -1.8, z: 0.0 });
+        assert_hit(found, Point { x: 1.75, y: 0.2, z: 0.25 });
+
+        //Crosses the whole grid above the terrain and leaves again.
+        let found = hit_point(&terrain(),
+                              Point { x: -1.0, y: 0.5, z: 0.25 },
+                              Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+        assert!(found.is_none(), "found {:?}", found);
+
+        //Passes beside the grid entirely.
+        let found = hit_point(&terrain(),
+                              Point { x: -1.0, y: 0.5, z: 3.0 },
+                              Vector3 { x: 1.0, y: -0.1, z: 0.0 });
+        assert!(found.is_none(), "found {:?}", found);
+    }
+
+    #[test]
+    fn heightfield_hit_along_cell_edges() {
+        //Runs along the edge between rows 0 and 1, and meets the slope up to the raised point.
+        let found = hit_point(&terrain(),
+                              Point { x: -1.0, y: 1.0, z: 1.0 },
+                              Vector3 { x: 1.0, y: -0.3, z: 0.0 });
+        assert_hit(found, Point { x: 15.0 / 11.0, y: 0.8 * 4.0 / 11.0, z: 1.0 });
+
+        //Runs along the edge between columns 0 and 1, and comes down on the flat ground.
+        let found = hit_point(&terrain(),
+                              Point { x: 1.0, y: 1.0, z: -1.0 },
+                              Vector3 { x: 0.0, y: -0.4, z: 1.0 });
+        assert_hit(found, Point { x: 1.0, y: 0.0, z: 1.5 });
+    }
+}
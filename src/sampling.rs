@@ -0,0 +1,94 @@
+use std::f64;
+
+const GOLDEN_ANGLE: f64 = 2.399963229728653;
+
+/// Returns the `index`th of `count` points spread evenly over the unit disk, laid out along
+/// Vogel's sunflower spiral. The spiral is turned and the points are handed out in a different
+/// order for each pixel seed. A single sample always lands in the center.
+pub fn disk_sample(seed: u32, index: u32, count: u32) -> (f64, f64) {
+    if count <= 1 {
+        return (0.0, 0.0);
+    }
+    let index = (index + scramble(seed, LENS_ORDER) % count) % count;
+    let turn = scramble(seed, LENS_TURN) as f64 / 4294967296.0 * 2.0 * f64::consts::PI;
+    let radius = ((index as f64 + 0.5) / count as f64).sqrt();
+    let theta = index as f64 * GOLDEN_ANGLE + turn;
+    (radius * theta.cos(), radius * theta.sin())
+}
+
+/// Mirrors the binary digits of `index` around the radix point, giving a low-discrepancy
+/// sequence in [0, 1). Consecutive indices are spread out rather than correlated with the disk
+/// samples above.
+pub fn radical_inverse(index: u32) -> f64 {
+    index.reverse_bits() as f64 / 4294967296.0
+}
+
+/// Returns how far through the shutter interval the `index`th of `count` samples is taken,
+/// from zero to one. The samples follow the radical inverse, shifted by a different amount
+/// for each pixel seed. A single sample is always taken as the shutter opens.
+pub fn shutter_sample(seed: u32, index: u32, count: u32) -> f64 {
+    if count <= 1 {
+        return 0.0;
+    }
+    let shift = scramble(seed, SHUTTER) as f64 / 4294967296.0;
+    let fraction = radical_inverse(index) + shift;
+    if fraction >= 1.0 {
+        fraction - 1.0
+    } else {
+        fraction
+    }
+}
+
+/// Scrambles the bits of a value, so that nearby inputs give unrelated outputs.
+fn hash(mut value: u32) -> u32 {
+    value ^= value >> 16;
+    value = value.wrapping_mul(0x7feb352d);
+    value ^= value >> 15;
+    value = value.wrapping_mul(0x846ca68b);
+    value ^= value >> 16;
+    value
+}
+
+/// The ways a pixel's samples are scrambled, each of which is given its own scramble.
+const PIXEL_CELL: u32 = 0;
+const PIXEL_X: u32 = 1;
+const PIXEL_Y: u32 = 2;
+const LENS_ORDER: u32 = 3;
+const LENS_TURN: u32 = 4;
+const SHUTTER: u32 = 5;
+
+/// Returns the seed that all of the samples for the pixel at (`x`, `y`) are scrambled with.
+/// Every sampled dimension gets its own scramble from it, so that the patterns the samples
+/// follow shift from pixel to pixel without lining up with each other.
+pub fn pixel_seed(x: u32, y: u32) -> u32 {
+    hash(x ^ hash(y))
+}
+
+/// Returns a value that looks random, depending only on the pixel's seed and the dimension.
+fn scramble(seed: u32, dimension: u32) -> u32 {
+    hash(seed ^ hash(dimension))
+}
+
+/// Returns a number in [0, 1) that looks random but depends only on its arguments, so renders
+/// come out the same every time.
+fn random(seed: u32, index: u32, dimension: u32) -> f64 {
+    scramble(hash(seed ^ index), dimension) as f64 / 4294967296.0
+}
+
+/// Returns where in a pixel the `index`th of `count` samples goes, as offsets from its top-left
+/// corner. The pixel is divided into a grid with a cell for each sample, and each sample lands
+/// at a random point in its own cell; counts that aren't square numbers leave some cells empty.
+/// A single sample always lands in the center.
+pub fn pixel_sample(seed: u32, index: u32, count: u32) -> (f64, f64) {
+    if count <= 1 {
+        return (0.5, 0.5);
+    }
+    let columns = (count as f64).sqrt().ceil() as u32;
+    let rows = (count + columns - 1) / columns;
+
+    let cell = (index + scramble(seed, PIXEL_CELL) % (columns * rows)) % (columns * rows);
+    let column = cell % columns;
+    let row = cell / columns;
+    ((column as f64 + random(seed, index, PIXEL_X)) / columns as f64,
+     (row as f64 + random(seed, index, PIXEL_Y)) / rows as f64)
+}
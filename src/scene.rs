@@ -1,12 +1,25 @@
 use point::Point;
 use vector::Vector3;
-use rendering::{Intersectable, Ray, TextureCoords};
+use rendering::Ray;
+pub use rendering::TextureCoords;
+use camera::Camera;
+use animation::Track;
+use mesh::{Mesh, load_mesh};
+use sdf::Sdf;
+use heightfield::{Heightfield, load_heightfield};
+use matrix::Matrix44;
+use bvh::Bvh;
+use std::collections::BTreeMap;
+use std::mem;
 use std::ops::{Add, Mul};
 use std::path::PathBuf;
+use std::sync::Arc;
 use image;
 use image::{DynamicImage, GenericImage, Pixel, Rgba};
 use std::fmt;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeStruct;
+use serde_json;
 
 const GAMMA: f32 = 2.2;
 
@@ -34,6 +47,16 @@ impl Color {
         }
     }
 
+    /// How different two colors look, from zero for the same color up to one for black and
+    /// white. Compares each channel as it would be displayed.
+    pub fn contrast(&self, other: &Color) -> f32 {
+        let (a, b) = (self.clamp(), other.clamp());
+        let difference = |a: f32, b: f32| (gamma_encode(a) - gamma_encode(b)).abs();
+        difference(a.red, b.red)
+            .max(difference(a.green, b.green))
+            .max(difference(a.blue, b.blue))
+    }
+
     pub fn to_rgba(&self) -> Rgba<u8> {
         Rgba::from_channels(
             (gamma_encode(self.red) * 255.0) as u8,
@@ -90,7 +113,7 @@ impl Add for Color {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Texture {
     pub path: PathBuf,
 
@@ -123,10 +146,13 @@ where
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum Coloration {
     Color(Color),
     Texture(#[serde(deserialize_with = "load_texture")] Texture),
+    /// Blends the colors of the vertices of each face, for meshes whose files give them. Other
+    /// surfaces are white.
+    VertexColor,
 }
 
 fn wrap(val: f32, bound: u32) -> u32 {
@@ -150,6 +176,14 @@ impl Coloration {
 
                 Color::from_rgba(texture.texture.get_pixel(tex_x, tex_y))
             }
+            //Only an intersection knows the vertex colors around it; see `Intersection::color`.
+            Coloration::VertexColor => {
+                Color {
+                    red: 1.0,
+                    green: 1.0,
+                    blue: 1.0,
+                }
+            }
         }
     }
 }
@@ -161,7 +195,7 @@ pub enum SurfaceType {
     Refractive { index: f32, transparency: f32 },
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Material {
     pub coloration: Coloration,
     pub albedo: f32,
@@ -173,26 +207,412 @@ pub struct Sphere {
     pub center: Point,
     pub radius: f64,
     pub material: Material,
+    /// Distance the sphere moves per unit of shutter time.
+    #[serde(default = "Vector3::zero")]
+    pub velocity: Vector3,
+}
+impl Sphere {
+    pub fn center_at(&self, time: f64) -> Point {
+        self.center + self.velocity * time
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Plane {
     pub origin: Point,
+    /// Points away from the plane's front face, so a ray only sees the plane while travelling
+    /// the same way as the normal. The surface faces the opposite way.
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub normal: Vector3,
+    pub material: Material,
+    /// Distance the plane moves per unit of shutter time.
+    #[serde(default = "Vector3::zero")]
+    pub velocity: Vector3,
+    /// Makes the plane visible from behind as well as in front.
+    #[serde(default)]
+    pub double_sided: bool,
+}
+impl Plane {
+    pub fn origin_at(&self, time: f64) -> Point {
+        self.origin + self.velocity * time
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Triangle {
+    /// Corners of the triangle; the front face is the one they wind counter-clockwise around.
+    pub vertices: [Point; 3],
+    /// Per-vertex shading normals, interpolated across the face.
+    #[serde(default)]
+    pub normals: Option<[Vector3; 3]>,
+    /// Per-vertex texture coordinates, interpolated across the face.
+    #[serde(default)]
+    pub texture_coords: Option<[TextureCoords; 3]>,
+    pub material: Material,
+    /// Makes the back face visible too, shaded as if it were the front.
+    #[serde(default)]
+    pub double_sided: bool,
+}
+impl Triangle {
+    pub fn face_normal(&self) -> Vector3 {
+        let edge1 = self.vertices[1] - self.vertices[0];
+        let edge2 = self.vertices[2] - self.vertices[0];
+        edge1.cross(&edge2).normalize()
+    }
+
+    pub fn centroid(&self) -> Point {
+        let sum = (self.vertices[1] - self.vertices[0]) + (self.vertices[2] - self.vertices[0]);
+        self.vertices[0] + sum * (1.0 / 3.0)
+    }
+
+    /// Returns the barycentric weights of each vertex for a point on the triangle.
+    pub fn barycentric(&self, point: &Point) -> (f64, f64, f64) {
+        let edge1 = self.vertices[1] - self.vertices[0];
+        let edge2 = self.vertices[2] - self.vertices[0];
+        let to_point = *point - self.vertices[0];
+        let d11 = edge1.dot(&edge1);
+        let d12 = edge1.dot(&edge2);
+        let d22 = edge2.dot(&edge2);
+        let dp1 = to_point.dot(&edge1);
+        let dp2 = to_point.dot(&edge2);
+        let denom = d11 * d22 - d12 * d12;
+        let v = (d22 * dp1 - d12 * dp2) / denom;
+        let w = (d11 * dp2 - d12 * dp1) / denom;
+        (1.0 - v - w, v, w)
+    }
+}
+
+/// A rectangular box, given by its corners before rotation. It is called `Box` in scene files;
+/// the struct is named differently so it doesn't shadow `std::boxed::Box`.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Cuboid {
+    pub min: Point,
+    pub max: Point,
+    /// Rotation of the box about its center, in degrees around the X, Y and Z axes in turn.
+    #[serde(default = "Vector3::zero")]
+    pub rotation: Vector3,
+    pub material: Material,
+}
+impl Cuboid {
+    pub fn center(&self) -> Point {
+        self.min + (self.max - self.min) * 0.5
+    }
+
+    pub fn half_size(&self) -> Vector3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The rotation from the box's own axes into the world.
+    pub(crate) fn orientation(&self) -> Matrix44 {
+        Matrix44::rotate_x(self.rotation.x.to_radians()) *
+        Matrix44::rotate_y(self.rotation.y.to_radians()) *
+        Matrix44::rotate_z(self.rotation.z.to_radians())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Disk {
+    pub center: Point,
+    /// Points away from the disk's front face, as a plane's normal does, so a ray only sees the
+    /// disk while travelling the same way as the normal. The surface faces the opposite way.
     #[serde(deserialize_with = "Vector3::deserialize_normalized")]
     pub normal: Vector3,
+    pub radius: f64,
     pub material: Material,
+    /// Makes the disk visible from behind as well as in front.
+    #[serde(default)]
+    pub double_sided: bool,
+}
+
+fn default_capped() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Cylinder {
+    /// Center of the bottom end of the cylinder.
+    pub base: Point,
+    /// Direction from the bottom end of the cylinder to the top.
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub axis: Vector3,
+    pub radius: f64,
+    pub height: f64,
+    /// Whether the ends are closed off; an uncapped cylinder is an open tube.
+    #[serde(default = "default_capped")]
+    pub capped: bool,
+    pub material: Material,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Cone {
+    /// Center of the cone's circular base.
+    pub base: Point,
+    /// Direction from the base towards the tip.
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub axis: Vector3,
+    /// Radius of the base.
+    pub radius: f64,
+    pub height: f64,
+    /// Whether the base is closed off.
+    #[serde(default = "default_capped")]
+    pub capped: bool,
+    pub material: Material,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Torus {
+    pub center: Point,
+    /// Direction through the hole of the torus.
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub axis: Vector3,
+    /// Distance from the center to the middle of the tube.
+    pub major_radius: f64,
+    /// Radius of the tube.
+    pub minor_radius: f64,
+    pub material: Material,
+}
+
+fn default_scale() -> Vector3 {
+    Vector3 {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    }
+}
+
+/// Another element placed in the scene with its own transform. In a scene file the element is
+/// either written out in full or given as the name of one of the scene's `definitions`. Every
+/// instance of a definition shares its one copy, so a mesh can be placed many times but only
+/// loaded once.
+#[derive(Debug)]
+pub struct Instance {
+    /// Name of the scene definition this places, or `None` if the element is the instance's own.
+    pub definition: Option<String>,
+    pub translate: Vector3,
+    /// Rotation in degrees around the X, Y and Z axes in turn.
+    pub rotate: Vector3,
+    pub scale: Vector3,
+    /// Replaces the material of the wrapped element when set.
+    pub material: Option<Material>,
+
+    //Instances that name a definition only get its element once the whole scene is loaded.
+    element: Option<Arc<Element>>,
+    to_object: Matrix44,
+}
+impl Instance {
+    pub fn new(element: Arc<Element>,
+               translate: Vector3,
+               rotate: Vector3,
+               scale: Vector3,
+               material: Option<Material>)
+               -> Instance {
+        let mut instance = Instance {
+            definition: None,
+            translate: translate,
+            rotate: rotate,
+            scale: scale,
+            material: material,
+            element: Some(element),
+            to_object: Matrix44::identity(),
+        };
+        instance.update_transform();
+        instance
+    }
+
+    /// The wrapped element. This panics for an instance of a definition that was loaded on its
+    /// own rather than as part of a scene.
+    pub fn element(&self) -> &Element {
+        match self.element {
+            Some(ref element) => element,
+            None => {
+                panic!("Instance of {:?} was never given its definition",
+                       self.definition)
+            }
+        }
+    }
+
+    /// Rebuilds the transform matrices; call this after changing the translation, rotation or
+    /// scale.
+    pub fn update_transform(&mut self) {
+        let to_world = Matrix44::scale(self.scale.x, self.scale.y, self.scale.z) *
+                       Matrix44::rotate_x(self.rotate.x.to_radians()) *
+                       Matrix44::rotate_y(self.rotate.y.to_radians()) *
+                       Matrix44::rotate_z(self.rotate.z.to_radians()) *
+                       Matrix44::translate(self.translate.x, self.translate.y, self.translate.z);
+        self.to_object = to_world.inverse();
+    }
+
+    pub(crate) fn to_object(&self) -> Matrix44 {
+        self.to_object
+    }
+}
+
+/// An instance as it is written in a file, where `element` is either an element or a name.
+#[derive(Deserialize)]
+struct InstanceFile {
+    element: serde_json::Value,
+    #[serde(default = "Vector3::zero")]
+    translate: Vector3,
+    #[serde(default = "Vector3::zero")]
+    rotate: Vector3,
+    #[serde(default = "default_scale")]
+    scale: Vector3,
+    #[serde(default)]
+    material: Option<Material>,
+}
+impl Deserialize for Instance {
+    fn deserialize<D>(deserializer: D) -> Result<Instance, D::Error>
+        where D: Deserializer
+    {
+        let file = InstanceFile::deserialize(deserializer)?;
+        let (definition, element) = match file.element {
+            serde_json::Value::String(name) => (Some(name), None),
+            value => {
+                let element: Element = serde_json::from_value(value)
+                    .map_err(::serde::de::Error::custom)?;
+                (None, Some(Arc::new(element)))
+            }
+        };
+        let mut instance = Instance {
+            definition: definition,
+            translate: file.translate,
+            rotate: file.rotate,
+            scale: file.scale,
+            material: file.material,
+            element: element,
+            to_object: Matrix44::identity(),
+        };
+        instance.update_transform();
+        Ok(instance)
+    }
+}
+impl Serialize for Instance {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut state = serializer.serialize_struct("Instance", 5)?;
+        match self.definition {
+            Some(ref name) => state.serialize_field("element", name)?,
+            None => state.serialize_field("element", &self.element)?,
+        }
+        state.serialize_field("translate", &self.translate)?;
+        state.serialize_field("rotate", &self.rotate)?;
+        state.serialize_field("scale", &self.scale)?;
+        state.serialize_field("material", &self.material)?;
+        state.end()
+    }
+}
+
+/// Elements named in a scene file's `definitions`, which are looked up as instances refer to
+/// them so that definitions can themselves hold instances of other definitions.
+struct Definitions {
+    unresolved: BTreeMap<String, Element>,
+    resolved: BTreeMap<String, Arc<Element>>,
+}
+impl Definitions {
+    fn get(&mut self, name: &str) -> Result<Arc<Element>, String> {
+        if let Some(element) = self.resolved.get(name) {
+            return Ok(element.clone());
+        }
+        let mut element = match self.unresolved.remove(name) {
+            Some(element) => element,
+            None => return Err(format!("Definition {:?} is missing or refers to itself", name)),
+        };
+        self.resolve_instances(&mut element)?;
+        let element = Arc::new(element);
+        self.resolved.insert(name.to_string(), element.clone());
+        Ok(element)
+    }
+
+    /// Gives every instance in the element that names a definition the element it names.
+    fn resolve_instances(&mut self, element: &mut Element) -> Result<(), String> {
+        match *element {
+            Element::Instance(ref mut i) => {
+                match i.definition {
+                    Some(ref name) => i.element = Some(self.get(name)?),
+                    None => {
+                        //Elements written out in an instance are only ever held by it.
+                        if let Some(element) = i.element.as_mut().and_then(Arc::get_mut) {
+                            self.resolve_instances(element)?;
+                        }
+                    }
+                }
+            }
+            Element::Csg(ref mut c) => {
+                self.resolve_instances(&mut c.left)?;
+                self.resolve_instances(&mut c.right)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum CsgOperation {
+    /// Everything inside either element.
+    Union,
+    /// Only what is inside both elements.
+    Intersection,
+    /// What is inside the left element but not the right.
+    Difference,
+}
+
+/// Two elements combined into a single solid. Only elements that enclose a volume take part:
+/// spheres, boxes, capped cylinders and cones, tori, planes (as the half-space behind their
+/// visible face), and instances or combinations of those.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Box<Element>,
+    pub right: Box<Element>,
+    /// Replaces the materials of both elements when set.
+    #[serde(default)]
+    pub material: Option<Material>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub enum Element {
     Sphere(Sphere),
     Plane(Plane),
+    Triangle(Triangle),
+    Box(Cuboid),
+    Disk(Disk),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Mesh(#[serde(deserialize_with = "load_mesh")] Mesh),
+    Instance(Instance),
+    Csg(Csg),
+    Sdf(Sdf),
+    Heightfield(#[serde(deserialize_with = "load_heightfield")] Heightfield),
 }
 impl Element {
     pub fn material(&self) -> &Material {
         match *self {
             Element::Sphere(ref s) => &s.material,
             Element::Plane(ref p) => &p.material,
+            Element::Triangle(ref t) => &t.material,
+            Element::Box(ref b) => &b.material,
+            Element::Disk(ref d) => &d.material,
+            Element::Cylinder(ref c) => &c.material,
+            Element::Cone(ref c) => &c.material,
+            Element::Torus(ref t) => &t.material,
+            Element::Mesh(ref m) => &m.material,
+            Element::Instance(ref i) => {
+                match i.material {
+                    Some(ref material) => material,
+                    None => i.element().material(),
+                }
+            }
+            Element::Csg(ref c) => {
+                match c.material {
+                    Some(ref material) => material,
+                    None => c.left.material(),
+                }
+            }
+            Element::Sdf(ref s) => &s.material,
+            Element::Heightfield(ref h) => &h.material,
         }
     }
 
@@ -200,6 +620,96 @@ impl Element {
         match *self {
             Element::Sphere(ref mut s) => &mut s.material,
             Element::Plane(ref mut p) => &mut p.material,
+            Element::Triangle(ref mut t) => &mut t.material,
+            Element::Box(ref mut b) => &mut b.material,
+            Element::Disk(ref mut d) => &mut d.material,
+            Element::Cylinder(ref mut c) => &mut c.material,
+            Element::Cone(ref mut c) => &mut c.material,
+            Element::Torus(ref mut t) => &mut t.material,
+            Element::Mesh(ref mut m) => &mut m.material,
+            Element::Instance(ref mut i) => {
+                //The wrapped element may be shared, so changes go into this instance's own
+                //copy of its material.
+                if i.material.is_none() {
+                    i.material = Some(i.element().material().clone());
+                }
+                i.material.as_mut().unwrap()
+            }
+            Element::Csg(ref mut c) => {
+                if c.material.is_none() {
+                    c.material = Some(c.left.material().clone());
+                }
+                c.material.as_mut().unwrap()
+            }
+            Element::Sdf(ref mut s) => &mut s.material,
+            Element::Heightfield(ref mut h) => &mut h.material,
+        }
+    }
+
+    pub fn set_center(&mut self, center: Point) {
+        match *self {
+            Element::Sphere(ref mut s) => s.center = center,
+            Element::Plane(ref mut p) => p.origin = center,
+            Element::Triangle(ref mut t) => {
+                let offset = center - t.centroid();
+                for vertex in t.vertices.iter_mut() {
+                    *vertex = *vertex + offset;
+                }
+            }
+            Element::Box(ref mut b) => {
+                let offset = center - b.center();
+                b.min = b.min + offset;
+                b.max = b.max + offset;
+            }
+            Element::Disk(ref mut d) => d.center = center,
+            Element::Cylinder(ref mut c) => c.base = center - c.axis * (c.height / 2.0),
+            Element::Cone(ref mut c) => c.base = center - c.axis * (c.height / 2.0),
+            Element::Torus(ref mut t) => t.center = center,
+            Element::Mesh(ref mut m) => {
+                let offset = center - m.data.centroid();
+                for position in m.data.positions.iter_mut() {
+                    *position = *position + offset;
+                }
+                m.build_bvh();
+            }
+            Element::Instance(ref mut i) => {
+                i.translate = center - Point::zero();
+                i.update_transform();
+            }
+            Element::Csg(ref mut c) => {
+                let offset = center - c.left.center();
+                let left_center = c.left.center() + offset;
+                let right_center = c.right.center() + offset;
+                c.left.set_center(left_center);
+                c.right.set_center(right_center);
+            }
+            Element::Sdf(ref mut s) => {
+                let offset = center - s.shape.center();
+                s.shape.translate(offset);
+            }
+            Element::Heightfield(ref mut h) => {
+                let offset = center - h.center();
+                h.origin = h.origin + offset;
+            }
+        }
+    }
+
+    /// The point that `set_center` moves.
+    pub fn center(&self) -> Point {
+        match *self {
+            Element::Sphere(ref s) => s.center,
+            Element::Plane(ref p) => p.origin,
+            Element::Triangle(ref t) => t.centroid(),
+            Element::Box(ref b) => b.center(),
+            Element::Disk(ref d) => d.center,
+            Element::Cylinder(ref c) => c.base + c.axis * (c.height / 2.0),
+            Element::Cone(ref c) => c.base + c.axis * (c.height / 2.0),
+            Element::Torus(ref t) => t.center,
+            Element::Mesh(ref m) => m.data.centroid(),
+            Element::Instance(ref i) => Point::zero() + i.translate,
+            Element::Csg(ref c) => c.left.center(),
+            Element::Sdf(ref s) => s.shape.center(),
+            Element::Heightfield(ref h) => h.center(),
         }
     }
 }
@@ -249,6 +759,13 @@ impl Light {
         }
     }
 
+    pub fn set_intensity(&mut self, intensity: f32) {
+        match *self {
+            Light::Directional(ref mut d) => d.intensity = intensity,
+            Light::Spherical(ref mut s) => s.intensity = intensity,
+        }
+    }
+
     pub fn distance(&self, hit_point: &Point) -> f64 {
         match *self {
             Light::Directional(_) => ::std::f64::INFINITY,
@@ -257,43 +774,325 @@ impl Light {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Serialize, Debug)]
 pub struct Scene {
     pub width: u32,
     pub height: u32,
-    pub fov: f64,
+    /// Defaults to a camera at the origin looking down -Z, which is where the camera was fixed
+    /// before it could be moved.
+    pub camera: Camera,
+    /// Elements that instances can place by name instead of each holding a copy.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub definitions: BTreeMap<String, Arc<Element>>,
     pub elements: Vec<Element>,
     pub lights: Vec<Light>,
 
     pub shadow_bias: f64,
     pub max_recursion_depth: u32,
+
+    /// Number of rays traced through each pixel and averaged. They are spread over the pixel's
+    /// area, the camera's lens and its shutter interval.
+    #[serde(default = "default_samples_per_pixel")]
+    pub samples_per_pixel: u32,
+    /// Samples only the pixels that need it instead of every pixel. Takes the place of
+    /// `samples_per_pixel` when set.
+    #[serde(default)]
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+
+    #[serde(default)]
+    pub animation: Vec<Track>,
+
+    /// Speeds up `trace`. It is built when the scene is loaded; see `build_bvh`.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub bvh: Bvh,
+}
+fn default_samples_per_pixel() -> u32 {
+    1
+}
+
+/// A scene as it is written in a file. Files from before the camera could be moved have no
+/// camera, only a top-level `fov`.
+#[derive(Deserialize)]
+struct SceneFile {
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    camera: Option<Camera>,
+    #[serde(default)]
+    fov: Option<f64>,
+    #[serde(default)]
+    definitions: BTreeMap<String, Element>,
+    elements: Vec<Element>,
+    lights: Vec<Light>,
+    shadow_bias: f64,
+    max_recursion_depth: u32,
+    #[serde(default = "default_samples_per_pixel")]
+    samples_per_pixel: u32,
+    #[serde(default)]
+    adaptive_sampling: Option<AdaptiveSampling>,
+    #[serde(default)]
+    animation: Vec<Track>,
+}
+impl Deserialize for Scene {
+    fn deserialize<D>(deserializer: D) -> Result<Scene, D::Error>
+        where D: Deserializer
+    {
+        let file = SceneFile::deserialize(deserializer)?;
+        let camera = match (file.camera, file.fov) {
+            (Some(camera), _) => camera,
+            (None, Some(fov)) => Camera { fov: fov, ..Camera::default() },
+            (None, None) => Camera::default(),
+        };
+
+        let mut definitions = Definitions {
+            resolved: BTreeMap::new(),
+            unresolved: file.definitions,
+        };
+        let names: Vec<String> = definitions.unresolved.keys().cloned().collect();
+        for name in &names {
+            definitions.get(name).map_err(::serde::de::Error::custom)?;
+        }
+        let mut elements = file.elements;
+        for element in &mut elements {
+            definitions.resolve_instances(element).map_err(::serde::de::Error::custom)?;
+        }
+
+        let mut scene = Scene {
+            width: file.width,
+            height: file.height,
+            camera: camera,
+            definitions: definitions.resolved,
+            elements: elements,
+            lights: file.lights,
+            shadow_bias: file.shadow_bias,
+            max_recursion_depth: file.max_recursion_depth,
+            samples_per_pixel: file.samples_per_pixel,
+            adaptive_sampling: file.adaptive_sampling,
+            animation: file.animation,
+            bvh: Bvh::default(),
+        };
+        for track in &scene.animation {
+            track.check(&scene).map_err(::serde::de::Error::custom)?;
+        }
+        scene.build_bvh();
+        Ok(scene)
+    }
+}
+
+/// Renders one sample through the center of each pixel, then goes back over the pixels whose
+/// color contrasts with a neighbor's by more than `threshold` and samples them more finely.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct AdaptiveSampling {
+    /// Largest difference in color, from zero to one, that is left alone.
+    #[serde(default = "default_adaptive_threshold")]
+    pub threshold: f32,
+    /// How many times a pixel may be split into quarters.
+    #[serde(default = "default_adaptive_max_depth")]
+    pub max_depth: u32,
+}
+fn default_adaptive_threshold() -> f32 {
+    0.1
+}
+fn default_adaptive_max_depth() -> u32 {
+    2
 }
 
 pub struct Intersection<'a> {
     pub distance: f64,
     pub element: &'a Element,
+    pub material: &'a Material,
+    pub normal: Vector3,
+    pub texture_coords: TextureCoords,
+    /// Color interpolated from the element's vertex colors, used by the `VertexColor`
+    /// coloration.
+    pub vertex_color: Option<Color>,
 
     //Prevent outside code from constructing this; should use the new method and check the distance.
     _secret: (),
 }
 impl<'a> Intersection<'a> {
-    pub fn new<'b>(distance: f64, element: &'b Element) -> Intersection<'b> {
+    pub fn new<'b>(distance: f64,
+                   element: &'b Element,
+                   normal: Vector3,
+                   texture_coords: TextureCoords)
+                   -> Intersection<'b> {
         if !distance.is_finite() {
             panic!("Intersection must have a finite distance.");
         }
         Intersection {
             distance: distance,
             element: element,
+            material: element.material(),
+            normal: normal,
+            texture_coords: texture_coords,
+            vertex_color: None,
             _secret: (),
         }
     }
+
+    pub fn color(&self) -> Color {
+        match (&self.material.coloration, self.vertex_color) {
+            (&Coloration::VertexColor, Some(color)) => color,
+            (coloration, _) => coloration.color(&self.texture_coords),
+        }
+    }
 }
 
 impl Scene {
+    /// Moves every animated property of the scene to its value at the given frame.
+    pub fn set_frame(&mut self, frame: f64) {
+        let animation = mem::replace(&mut self.animation, vec![]);
+        for track in &animation {
+            track.apply(self, frame);
+        }
+        self.animation = animation;
+        self.build_bvh();
+    }
+
+    /// Rebuilds the bounding volume hierarchy over the elements. This must be done after
+    /// adding, removing or moving any of them through `elements`.
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<_> = self.elements.iter().map(|e| e.bounding_box()).collect();
+        self.bvh = Bvh::build(&bounds);
+    }
+
+    /// Adds an element and rebuilds the bounding volume hierarchy to take it in.
+    pub fn add_element(&mut self, element: Element) {
+        self.elements.push(element);
+        self.build_bvh();
+    }
+
     pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
-        self.elements
-            .iter()
-            .filter_map(|e| e.intersect(ray).map(|d| Intersection::new(d, e)))
-            .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+        self.bvh
+            .nearest(ray, |i| {
+                self.elements.get(i).and_then(|e| e.trace(ray)).map(|hit| (hit.distance, hit))
+            })
+            .map(|(_, hit)| hit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    const EMPTY_SCENE: &'static str = r#""width": 4, "height": 3, "elements": [], "lights": [],
+                                         "shadow_bias": 1e-13, "max_recursion_depth": 1"#;
+
+    #[test]
+    fn legacy_fov_moves_into_the_default_camera() {
+        let scene: Scene = serde_json::from_str(&format!("{{{}, \"fov\": 60.0}}", EMPTY_SCENE))
+            .unwrap();
+        assert_eq!(scene.camera.fov, 60.0);
+        assert_eq!(scene.camera.position.z, 0.0);
+        assert_eq!(scene.camera.look_at.z, -1.0);
+    }
+
+    #[test]
+    fn camera_defaults_when_missing() {
+        let scene: Scene = serde_json::from_str(&format!("{{{}}}", EMPTY_SCENE)).unwrap();
+        assert_eq!(scene.camera.fov, 90.0);
+        assert_eq!(scene.camera.up.y, 1.0);
+    }
+
+    fn sphere_json(z: f64) -> String {
+        format!(r#"{{"Sphere": {{"center": {{"x": 0.0, "y": 0.0, "z": {}}},
+                                "radius": 1.0,
+                                "material": {{"coloration": {{"Color": {{"red": 1.0,
+                                                                       "green": 1.0,
+                                                                       "blue": 1.0}}}},
+                                             "albedo": 0.18,
+                                             "surface": "Diffuse"}}}}}}"#,
+                z)
+    }
+
+    fn sphere(z: f64) -> Element {
+        serde_json::from_str(&sphere_json(z)).unwrap()
+    }
+
+    fn instance(element: &str, z: f64) -> String {
+        format!(r#"{{"Instance": {{"element": {},
+                                  "translate": {{"x": 0.0, "y": 0.0, "z": {}}}}}}}"#,
+                element,
+                z)
+    }
+
+    fn load_with_definitions(definitions: &str, elements: &[String]) -> Result<Scene, String> {
+        let json = format!("{{{}, \"definitions\": {{{}}}}}",
+                           EMPTY_SCENE.replace("\"elements\": []",
+                                               &format!("\"elements\": [{}]", elements.join(","))),
+                           definitions);
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+
+    fn placed(element: &Element) -> *const Element {
+        match *element {
+            Element::Instance(ref i) => i.element(),
+            _ => panic!("expected an instance"),
+        }
+    }
+
+    fn trace_forward(scene: &Scene) -> Option<f64> {
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+            time: 0.0,
+        };
+        scene.trace(&ray).map(|hit| hit.distance)
+    }
+
+    #[test]
+    fn elements_are_traceable_once_loaded_or_added() {
+        let mut scene: Scene = serde_json::from_str(&format!("{{{}}}", EMPTY_SCENE)).unwrap();
+        scene.elements.push(sphere(-10.0));
+        scene.build_bvh();
+        let json = serde_json::to_string(&scene).unwrap();
+
+        let mut scene: Scene = serde_json::from_str(&json).unwrap();
+        assert_eq!(trace_forward(&scene), Some(9.0));
+        scene.add_element(sphere(-5.0));
+        assert_eq!(trace_forward(&scene), Some(4.0));
+    }
+
+    #[test]
+    fn instances_share_definitions_within_a_scene() {
+        let definitions = format!(r#""ball": {}"#, sphere_json(0.0));
+        let elements = [instance(r#""ball""#, -5.0),
+                        instance(r#""ball""#, -10.0),
+                        instance(&sphere_json(0.0), -15.0)];
+        let scene = load_with_definitions(&definitions, &elements).unwrap();
+        let ball: *const Element = &*scene.definitions["ball"];
+        assert_eq!(placed(&scene.elements[0]), ball);
+        assert_eq!(placed(&scene.elements[1]), ball);
+        assert!(placed(&scene.elements[2]) != ball);
+        assert_eq!(trace_forward(&scene), Some(4.0));
+
+        //Loading the same file again doesn't reuse anything from the first scene.
+        let other = load_with_definitions(&definitions, &elements).unwrap();
+        assert!(placed(&other.elements[0]) != ball);
+    }
+
+    #[test]
+    fn definitions_can_place_other_definitions() {
+        let definitions = format!(r#""ball": {}, "moved": {}"#,
+                                  sphere_json(0.0),
+                                  instance(r#""ball""#, -5.0));
+        let scene = load_with_definitions(&definitions, &[instance(r#""moved""#, -5.0)]).unwrap();
+        assert_eq!(trace_forward(&scene), Some(9.0));
+
+        //Names are written back out, so the definitions stay shared.
+        let json = serde_json::to_string(&scene).unwrap();
+        let scene: Scene = serde_json::from_str(&json).unwrap();
+        assert_eq!(placed(&scene.elements[0]), &*scene.definitions["moved"] as *const Element);
+        assert_eq!(trace_forward(&scene), Some(9.0));
+    }
+
+    #[test]
+    fn missing_and_circular_definitions_are_rejected() {
+        assert!(load_with_definitions("", &[instance(r#""ball""#, -5.0)]).is_err());
+        let circular = format!(r#""a": {}, "b": {}"#,
+                               instance(r#""b""#, 1.0),
+                               instance(r#""a""#, 1.0));
+        assert!(load_with_definitions(&circular, &[]).is_err());
     }
 }
@@ -0,0 +1,254 @@
+//! Shapes described by signed distance functions. These are rendered by sphere tracing rather
+//! than by solving for the intersection directly.
+
+use point::Point;
+use vector::Vector3;
+use scene::Material;
+use bvh::BoundingBox;
+
+fn default_power() -> f64 {
+    8.0
+}
+
+fn default_iterations() -> u32 {
+    8
+}
+
+fn default_max_distance() -> f64 {
+    1000.0
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum SdfShape {
+    Sphere { center: Point, radius: f64 },
+    /// A box whose edges are rounded off by `radius`. `half_size` includes the rounding.
+    RoundedBox {
+        center: Point,
+        half_size: Vector3,
+        radius: f64,
+    },
+    /// A line segment thickened by `radius`.
+    Capsule { start: Point, end: Point, radius: f64 },
+    /// A torus around an axis parallel to Y.
+    Torus {
+        center: Point,
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    /// The union of two shapes, blended together over a distance of `smoothness`.
+    SmoothUnion {
+        left: Box<SdfShape>,
+        right: Box<SdfShape>,
+        smoothness: f64,
+    },
+    /// Repeats a shape forever along each axis with a non-zero period.
+    Repeat { shape: Box<SdfShape>, period: Vector3 },
+    Mandelbulb {
+        center: Point,
+        scale: f64,
+        #[serde(default = "default_power")]
+        power: f64,
+        #[serde(default = "default_iterations")]
+        iterations: u32,
+    },
+}
+
+fn repeat(value: f64, period: f64) -> f64 {
+    if period > 0.0 {
+        value - period * (value / period).round()
+    } else {
+        value
+    }
+}
+
+impl SdfShape {
+    /// Returns the distance from the point to the surface, negative inside. This may
+    /// underestimate the distance, but never overestimates it.
+    pub fn distance(&self, point: &Point) -> f64 {
+        match *self {
+            SdfShape::Sphere { center, radius } => (*point - center).length() - radius,
+            SdfShape::RoundedBox { center, half_size, radius } => {
+                let p = *point - center;
+                let q = Vector3 {
+                    x: p.x.abs() - half_size.x + radius,
+                    y: p.y.abs() - half_size.y + radius,
+                    z: p.z.abs() - half_size.z + radius,
+                };
+                let outside = Vector3 {
+                        x: q.x.max(0.0),
+                        y: q.y.max(0.0),
+                        z: q.z.max(0.0),
+                    }
+                    .length();
+                let inside = q.x.max(q.y).max(q.z).min(0.0);
+                outside + inside - radius
+            }
+            SdfShape::Capsule { start, end, radius } => {
+                let to_point = *point - start;
+                let segment = end - start;
+                let h = (to_point.dot(&segment) / segment.dot(&segment)).max(0.0).min(1.0);
+                (to_point - segment * h).length() - radius
+            }
+            SdfShape::Torus { center, major_radius, minor_radius } => {
+                let p = *point - center;
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            }
+            SdfShape::SmoothUnion { ref left, ref right, smoothness } => {
+                let d1 = left.distance(point);
+                let d2 = right.distance(point);
+                if smoothness <= 0.0 {
+                    return d1.min(d2);
+                }
+                let h = (0.5 + 0.5 * (d2 - d1) / smoothness).max(0.0).min(1.0);
+                d2 + (d1 - d2) * h - smoothness * h * (1.0 - h)
+            }
+            SdfShape::Repeat { ref shape, period } => {
+                let cell = Point {
+                    x: repeat(point.x, period.x),
+                    y: repeat(point.y, period.y),
+                    z: repeat(point.z, period.z),
+                };
+                shape.distance(&cell)
+            }
+            SdfShape::Mandelbulb { center, scale, power, iterations } => {
+                let c = (*point - center) * (1.0 / scale);
+                let mut z = c;
+                let mut dr = 1.0;
+                let mut r = z.length();
+                for _ in 0..iterations {
+                    if r > 2.0 {
+                        break;
+                    }
+                    //Near the origin z has no direction to take the angles of, but any power of
+                    //it is still the origin.
+                    if r < 1e-12 {
+                        z = c;
+                        dr = 1.0;
+                        r = z.length();
+                        continue;
+                    }
+                    //Raise z to the given power in spherical coordinates, then add c.
+                    let theta = (z.z / r).acos() * power;
+                    let phi = z.y.atan2(z.x) * power;
+                    dr = r.powf(power - 1.0) * power * dr + 1.0;
+                    let zr = r.powf(power);
+                    z = Vector3 {
+                        x: zr * theta.sin() * phi.cos(),
+                        y: zr * theta.sin() * phi.sin(),
+                        z: zr * theta.cos(),
+                    } + c;
+                    r = z.length();
+                }
+                if r == 0.0 {
+                    return -scale;
+                }
+                0.5 * r.ln() * r / dr * scale
+            }
+        }
+    }
+
+    pub fn center(&self) -> Point {
+        match *self {
+            SdfShape::Sphere { center, .. } |
+            SdfShape::RoundedBox { center, .. } |
+            SdfShape::Torus { center, .. } |
+            SdfShape::Mandelbulb { center, .. } => center,
+            SdfShape::Capsule { start, end, .. } => start + (end - start) * 0.5,
+            SdfShape::SmoothUnion { ref left, .. } => left.center(),
+            SdfShape::Repeat { ref shape, .. } => shape.center(),
+        }
+    }
+
+    /// Returns a box the shape lies within, or `None` if it goes on forever.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        match *self {
+            SdfShape::Sphere { center, radius } => {
+                Some(BoundingBox::around(&center, Vector3::from_one(radius)))
+            }
+            SdfShape::RoundedBox { center, half_size, .. } => {
+                Some(BoundingBox::around(&center, half_size))
+            }
+            SdfShape::Capsule { start, end, radius } => {
+                Some(BoundingBox::from_points(&[start, end]).expand(radius))
+            }
+            SdfShape::Torus { center, major_radius, minor_radius } => {
+                let extent = Vector3 {
+                    x: major_radius + minor_radius,
+                    y: minor_radius,
+                    z: major_radius + minor_radius,
+                };
+                Some(BoundingBox::around(&center, extent))
+            }
+            SdfShape::SmoothUnion { ref left, ref right, smoothness } => {
+                //Blending only ever adds material within `smoothness` of both shapes.
+                match (left.bounding_box(), right.bounding_box()) {
+                    (Some(left), Some(right)) => {
+                        Some(left.union(&right).expand(smoothness.max(0.0)))
+                    }
+                    _ => None,
+                }
+            }
+            SdfShape::Repeat { ref shape, period } => {
+                if period.x > 0.0 || period.y > 0.0 || period.z > 0.0 {
+                    None
+                } else {
+                    shape.bounding_box()
+                }
+            }
+            SdfShape::Mandelbulb { center, scale, .. } => {
+                //Points more than twice the scale away escape before the first iteration.
+                Some(BoundingBox::around(&center, Vector3::from_one(2.0 * scale)))
+            }
+        }
+    }
+
+    pub fn translate(&mut self, offset: Vector3) {
+        match *self {
+            SdfShape::Sphere { ref mut center, .. } |
+            SdfShape::RoundedBox { ref mut center, .. } |
+            SdfShape::Torus { ref mut center, .. } |
+            SdfShape::Mandelbulb { ref mut center, .. } => *center = *center + offset,
+            SdfShape::Capsule { ref mut start, ref mut end, .. } => {
+                *start = *start + offset;
+                *end = *end + offset;
+            }
+            SdfShape::SmoothUnion { ref mut left, ref mut right, .. } => {
+                left.translate(offset);
+                right.translate(offset);
+            }
+            SdfShape::Repeat { ref mut shape, .. } => shape.translate(offset),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Sdf {
+    pub shape: SdfShape,
+    pub material: Material,
+    /// How far along a ray to search for the surface before giving up.
+    #[serde(default = "default_max_distance")]
+    pub max_distance: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mandelbulb_distance_is_finite_at_its_center() {
+        let center = Point {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+        let bulb = SdfShape::Mandelbulb {
+            center: center,
+            scale: 2.0,
+            power: 8.0,
+            iterations: 10,
+        };
+        assert!(bulb.distance(&center).is_finite());
+        assert!(bulb.distance(&center).is_sign_negative());
+    }
+}